@@ -1,25 +1,178 @@
-use egui::{Color32, Context, Vec2};
-use egui_file_dialog::FileDialog;
+use crate::debugger::{WatchKind, Watchpoint};
+use crate::coverage::Coverage;
+use crate::generator::text_to_brainfuck;
+use crate::profiler::Profile;
+use crate::machine::{CellWidth, EofBehavior, Machine, OverflowPolicy, TapeMode};
+use egui::text::{CCursor, LayoutJob, TextFormat};
+use egui::{Color32, Context, Key, KeyboardShortcut, Modifiers, Vec2};
+use egui_file_dialog::{DialogMode, DialogState, FileDialog};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{fs, thread};
 
+/// The code editor keeps this id whether it is editable or not, so its cursor survives a run.
+/// Each tab adds its own number to it.
+const CODE_EDITOR_ID: &str = "code_editor";
+
+/// Number of the next tab, so tabs do not share the state of their widgets.
+static NEXT_TAB: AtomicUsize = AtomicUsize::new(0);
+
+/// Height of the code editor before it starts scrolling.
+const CODE_VIEW_HEIGHT: f32 = 300.0;
+
+/// Colors of the kinds of commands in the code editor. Brackets use the strong text
+/// color and comments the weak one, so they follow the theme.
+const MOVE_COLOR: Color32 = Color32::from_rgb(86, 156, 214);
+const ARITHMETIC_COLOR: Color32 = Color32::from_rgb(214, 140, 60);
+const IO_COLOR: Color32 = Color32::from_rgb(190, 110, 200);
+
+/// Number of loops listed under "Profile".
+const PROFILE_LOOPS_SHOWN: usize = 5;
+
+const SAVE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::S);
+const SAVE_AS_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::S);
+
+/// What the unsaved changes prompt was asked about, done once the user decides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnsavedAction {
+    Open,
+    CloseTab,
+    Quit,
+}
+
+/// The window: a tab for every open program and the parts they share.
 /// We derive Deserialize/Serialize, so we can persist app state on shutdown.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct BrainfuckInterpreterApp {
+    pub(crate) tabs: Vec<BrainfuckInterpreterInterface>,
+    /// Index of the tab that is shown.
+    pub(crate) active: usize,
+    /// Kept in sync with egui's, which is what the theme buttons change.
+    theme: egui::ThemePreference,
+    /// The window title last sent to the viewport.
+    #[serde(skip)]
+    title: String,
+}
+
+impl Default for BrainfuckInterpreterApp {
+    fn default() -> Self {
+        Self {
+            tabs: vec![BrainfuckInterpreterInterface::default()],
+            active: 0,
+            theme: egui::ThemePreference::System,
+            title: "".to_string(),
+        }
+    }
+}
+
+/// One tab: a program with its own input, output, tape and interpreter thread.
+/// Only the code, input, delay, memory size and its +/- step (`power`), path and
+/// breakpoints are kept on shutdown, along with the code as last saved so unsaved
+/// changes stay marked.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct BrainfuckInterpreterInterface {
     path: String,
+    #[serde(skip)]
     file_dialog: FileDialog,
+    #[serde(skip)]
     pub(crate) letter_index: Arc<Mutex<usize>>,
+    #[serde(skip)]
     pub(crate) box_index: Arc<Mutex<usize>>,
     pub(crate) delay: Arc<Mutex<u64>>,
+    #[serde(skip)]
+    pub(crate) optimize: bool,
+    /// Whether `#` in the code pauses the run.
+    #[serde(skip)]
+    pub(crate) debug_dumps: bool,
+    /// Whether runs use native code, see [`Machine::with_jit`](crate::Machine::with_jit).
+    #[serde(skip)]
+    pub(crate) jit: bool,
+    /// Whether runs count how often each instruction runs.
+    #[serde(skip)]
+    pub(crate) profiling: bool,
+    /// Counts of the last profiled run, shown as a heat map over the code.
+    #[serde(skip)]
+    pub(crate) profile: Arc<Mutex<Option<Profile>>>,
+    /// Whether the code shows which commands ran over all runs since it last changed.
+    #[serde(skip)]
+    pub(crate) coverage_enabled: bool,
+    /// Coverage of the runs before the current one.
+    #[serde(skip)]
+    pub(crate) coverage: Option<Coverage>,
+    /// Character positions to pause at, by file path. Unsaved code uses the empty path.
+    pub(crate) breakpoints: HashMap<String, BTreeSet<usize>>,
+    /// Set when a run pauses, so the code and memory scroll to the current location.
+    #[serde(skip)]
+    pub(crate) scroll_to_pointer: Arc<Mutex<bool>>,
+    /// Pause when this expression becomes true, see [`Condition`](crate::Condition).
+    #[serde(skip)]
+    pub(crate) break_condition: String,
+    #[serde(skip)]
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    /// What paused the run, empty if it was not a breakpoint or watchpoint.
+    #[serde(skip)]
+    pub(crate) pause_reason: Arc<Mutex<String>>,
     power: u32,
-    counter: usize,
     pub(crate) input_text: Arc<Mutex<String>>,
     pub(crate) input_brainfuck: Arc<Mutex<String>>,
+    /// The code as it was last loaded or saved, to tell whether it has unsaved changes.
+    saved_code: String,
+    /// Shown while this waits for the user to save or discard the changes.
+    #[serde(skip)]
+    unsaved_prompt: Option<UnsavedAction>,
+    /// Done once the "Save As" dialog the prompt opened has saved the code.
+    #[serde(skip)]
+    after_save: Option<UnsavedAction>,
+    /// Set once the user chose to quit anyway, so the close is not cancelled again.
+    #[serde(skip)]
+    close_confirmed: bool,
+    /// Set once the user chose to close the tab, which the app then removes.
+    #[serde(skip)]
+    closing: bool,
+    #[serde(skip)]
+    editor_id: egui::Id,
+    /// Whether the "Generate from text" window is open, and the text typed into it.
+    #[serde(skip)]
+    generator_open: bool,
+    #[serde(skip)]
+    generator_text: String,
+    #[serde(skip)]
     pub(crate) output: Arc<Mutex<String>>,
-    pub(crate) data: Arc<Mutex<Vec<u8>>>,
+    /// The bytes `output` was decoded from, so stepping back can drop exactly
+    /// what an instruction wrote.
+    #[serde(skip)]
+    pub(crate) output_bytes: Arc<Mutex<Vec<u8>>>,
+    /// Number of cells every run starts with. Older saves stored it as `data`.
+    #[serde(alias = "data")]
+    pub(crate) memory_size: usize,
+    /// The tape as the GUI shows it, which grows with the run.
+    #[serde(skip)]
+    pub(crate) data: Arc<Mutex<Vec<u64>>>,
+    #[serde(skip)]
+    pub(crate) tape_origin: Arc<Mutex<usize>>,
+    #[serde(skip)]
+    pub(crate) tape_mode: TapeMode,
+    #[serde(skip)]
+    pub(crate) cell_width: CellWidth,
+    #[serde(skip)]
+    pub(crate) overflow_policy: OverflowPolicy,
+    #[serde(skip)]
+    pub(crate) eof_behavior: EofBehavior,
+    #[serde(skip)]
     pub(crate) timer_running: Arc<Mutex<bool>>,
+    #[serde(skip)]
     pub(crate) warn: Arc<Mutex<bool>>,
+    #[serde(skip)]
     pub(crate) warn_message: Arc<Mutex<String>>,
+    #[serde(skip)]
     pub(crate) timer_thread_handle: Option<thread::JoinHandle<()>>,
+    #[serde(skip)]
+    pub(crate) machine: Arc<Mutex<Option<Machine>>>,
 }
 
 impl Default for BrainfuckInterpreterInterface {
@@ -35,88 +188,724 @@ impl Default for BrainfuckInterpreterInterface {
             letter_index: Arc::new(Mutex::new(0)),
             box_index: Arc::new(Mutex::new(0)),
             delay: Arc::new(Mutex::new(5u64)),
+            optimize: true,
+            debug_dumps: false,
+            jit: false,
+            profiling: false,
+            profile: Arc::new(Mutex::new(None)),
+            coverage_enabled: false,
+            coverage: None,
+            breakpoints: HashMap::new(),
+            scroll_to_pointer: Arc::new(Mutex::new(false)),
+            break_condition: "".to_string(),
+            watchpoints: Vec::new(),
+            pause_reason: Arc::new(Mutex::new("".to_string())),
             power: 0,
-            counter: 0,
             input_text: Arc::new(Mutex::new("".to_string())),
             input_brainfuck: Arc::new(Mutex::new("".to_string())),
+            saved_code: "".to_string(),
+            unsaved_prompt: None,
+            after_save: None,
+            close_confirmed: false,
+            closing: false,
+            editor_id: egui::Id::new(CODE_EDITOR_ID).with(NEXT_TAB.fetch_add(1, Ordering::Relaxed)),
+            generator_open: false,
+            generator_text: "".to_string(),
             output: Arc::new(Mutex::new("".to_string())),
+            output_bytes: Arc::new(Mutex::new(Vec::new())),
+            memory_size: 256,
             data: Arc::new(Mutex::new(vec![0; 256])),
+            tape_origin: Arc::new(Mutex::new(0)),
+            tape_mode: TapeMode::default(),
+            cell_width: CellWidth::default(),
+            overflow_policy: OverflowPolicy::default(),
+            eof_behavior: EofBehavior::default(),
             timer_running: Arc::new(Mutex::new(false)),
             warn: Arc::new(Mutex::new(false)),
             warn_message: Arc::new(Mutex::new("".to_string())),
             timer_thread_handle: None,
+            machine: Arc::new(Mutex::new(None)),
         }
     }
 }
 
-impl BrainfuckInterpreterInterface {
+impl BrainfuckInterpreterApp {
     /// Called once before the first frame.
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
-        Default::default()
+        let app = Self::restore(cc.storage);
+        cc.egui_ctx.set_theme(app.theme);
+        app
+    }
+
+    /// The app as it was last saved, or the default one.
+    pub(crate) fn restore(storage: Option<&dyn eframe::Storage>) -> Self {
+        let Some(storage) = storage else {
+            return Self::default();
+        };
+        let mut app: Self = eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        if app.tabs.is_empty() {
+            app.tabs.push(BrainfuckInterpreterInterface::default());
+        }
+        app.active = app.active.min(app.tabs.len() - 1);
+        for tab in &mut app.tabs {
+            tab.memory_size = tab.memory_size.max(2);
+            *tab.data.lock().unwrap() = vec![0; tab.memory_size];
+        }
+        app
+    }
+
+    pub(crate) fn new_tab(&mut self) {
+        self.tabs.push(BrainfuckInterpreterInterface::default());
+        self.active = self.tabs.len() - 1;
+    }
+
+    /// Closes the tab at `index`, asking first if it has unsaved changes.
+    pub(crate) fn close_tab(&mut self, index: usize) {
+        if self.tabs[index].is_dirty() {
+            self.active = index;
+            self.tabs[index].unsaved_prompt = Some(UnsavedAction::CloseTab);
+        } else {
+            self.tabs[index].closing = true;
+        }
+    }
+
+    /// Removes the tabs the user closed, stopping their runs. There is always one left.
+    pub(crate) fn remove_closed_tabs(&mut self) {
+        let mut index = 0;
+        while index < self.tabs.len() {
+            if self.tabs[index].closing {
+                self.tabs.remove(index).stop_interpreter();
+                if self.active > index {
+                    self.active -= 1;
+                }
+            } else {
+                index += 1;
+            }
+        }
+        if self.tabs.is_empty() {
+            self.tabs.push(BrainfuckInterpreterInterface::default());
+        }
+        self.active = self.active.min(self.tabs.len() - 1);
     }
 
+    /// The tab buttons, with a close button on each and one to add a tab.
+    fn tab_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            let mut closed = None;
+            for (index, tab) in self.tabs.iter().enumerate() {
+                let running = *tab.timer_running.lock().unwrap();
+                let name = if running {
+                    format!("▶ {}", tab.tab_name())
+                } else {
+                    tab.tab_name()
+                };
+                let hover = if tab.path.is_empty() {
+                    "Not saved yet"
+                } else {
+                    &tab.path
+                };
+                if ui.selectable_label(index == self.active, name).on_hover_text(hover).clicked() {
+                    self.active = index;
+                }
+                if ui.small_button("×").on_hover_text("Close tab").clicked() {
+                    closed = Some(index);
+                }
+                ui.separator();
+            }
+            if ui.button("+").on_hover_text("New tab").clicked() {
+                self.new_tab();
+            }
+            if let Some(index) = closed {
+                self.close_tab(index);
+            }
+        });
+    }
+}
+
+impl BrainfuckInterpreterInterface {
+    /// Breakpoints of the file that is currently open.
+    pub(crate) fn current_breakpoints(&self) -> BTreeSet<usize> {
+        self.breakpoints.get(&self.path).cloned().unwrap_or_default()
+    }
+
+    /// Removes the watchpoints on `cell`, or adds one that pauses when it changes.
+    fn toggle_watchpoint(&mut self, cell: isize) {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watch| watch.cell != cell);
+        if self.watchpoints.len() == count {
+            self.watchpoints.push(Watchpoint {
+                cell,
+                kind: WatchKind::Change,
+            });
+        }
+    }
+
+    fn toggle_breakpoint(&mut self, position: usize) {
+        let breakpoints = self.breakpoints.entry(self.path.clone()).or_default();
+        if !breakpoints.remove(&position) {
+            breakpoints.insert(position);
+        }
+    }
+
+    /// Loads the file picked in the file dialog.
     pub fn set_path_multi(&mut self, ctx: &Context) {
-        if let Some(path) = self.file_dialog.update(ctx).selected().clone() {
-            #[cfg(target_os = "windows")]
-            {
-                self.path = path
-                    .to_str()
-                    .unwrap_or_else(|| "Error: Invalid path")
-                    .to_string();
-                self.path = self.path[4..].to_string();
-                match fs::read_to_string(self.path.clone()) {
-                    Ok(content) => {
-                        // Filter symbols
-                        let filtered: String = content
-                            .chars()
-                            .filter(|c| {
-                                ['[', ']', '-', '>', '+', '<', '.', ','].contains(c)
-                            })
-                            .collect();
-                        if self.counter > 0 {
-                            self.input_brainfuck = Arc::new(Mutex::new(filtered));
-                            self.counter -= 1;
+        if self.file_dialog.update(ctx).mode() == DialogMode::SaveFile {
+            self.finish_save_as(ctx);
+            return;
+        }
+        if let Some(path) = self.file_dialog.take_selected() {
+            self.load(dialog_path(&path));
+        }
+    }
+
+    /// Replaces the code with the file at `path`, exactly as it is. Characters that
+    /// are not commands are comments and are skipped when the code runs.
+    pub(crate) fn load(&mut self, path: String) {
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                self.saved_code = content.clone();
+                self.input_brainfuck = Arc::new(Mutex::new(content));
+                self.path = path;
+            }
+            Err(error) => {
+                *self.warn_message.lock().unwrap() = format!("Could not open {path}: {error}");
+                *self.warn.lock().unwrap() = true;
+            }
+        }
+    }
+
+    /// Whether the code differs from what was last loaded or saved.
+    pub(crate) fn is_dirty(&self) -> bool {
+        *self.input_brainfuck.lock().unwrap() != self.saved_code
+    }
+
+    /// The file name, with a `*` in front when the code has unsaved changes.
+    pub(crate) fn tab_name(&self) -> String {
+        let name = Path::new(&self.path)
+            .file_name()
+            .map_or("Untitled".to_string(), |name| name.to_string_lossy().into_owned());
+        let dirty = if self.is_dirty() { "*" } else { "" };
+        format!("{dirty}{name}")
+    }
+
+    pub(crate) fn window_title(&self) -> String {
+        format!("{} - Brainfuck Interpreter", self.tab_name())
+    }
+
+    /// Writes the code to `path` and makes it the current file. Shows a warning
+    /// and returns false if the file could not be written.
+    pub(crate) fn save_to(&mut self, path: String) -> bool {
+        let code = self.input_brainfuck.lock().unwrap().clone();
+        if let Err(error) = fs::write(&path, &code) {
+            *self.warn_message.lock().unwrap() = format!("Could not save {path}: {error}");
+            *self.warn.lock().unwrap() = true;
+            return false;
+        }
+        if path != self.path {
+            // Breakpoints follow the code to its new file
+            let breakpoints = self.current_breakpoints();
+            if self.path.is_empty() {
+                self.breakpoints.remove("");
+            }
+            if !breakpoints.is_empty() {
+                self.breakpoints.insert(path.clone(), breakpoints);
+            }
+            self.path = path;
+        }
+        self.saved_code = code;
+        true
+    }
+
+    /// Saves to the current file, or asks for one if the code was never saved.
+    fn save_code(&mut self) -> bool {
+        if self.path.is_empty() {
+            self.save_as();
+            return false;
+        }
+        self.save_to(self.path.clone())
+    }
+
+    fn save_as(&mut self) {
+        let name = Path::new(&self.path)
+            .file_name()
+            .map_or("program.bf".to_string(), |name| name.to_string_lossy().into_owned());
+        self.file_dialog.config_mut().default_file_name = name;
+        self.file_dialog.save_file();
+    }
+
+    /// Saves to the file picked in the "Save As" dialog, if there is one yet.
+    fn finish_save_as(&mut self, ctx: &Context) {
+        if self.file_dialog.state() == DialogState::Cancelled {
+            self.after_save = None;
+        }
+        let Some(path) = self.file_dialog.take_selected() else {
+            return;
+        };
+        if self.save_to(dialog_path(&path)) {
+            if let Some(action) = self.after_save.take() {
+                self.continue_with(action, ctx);
+            }
+        }
+    }
+
+    fn open_file(&mut self, ctx: &Context) {
+        if self.is_dirty() {
+            self.unsaved_prompt = Some(UnsavedAction::Open);
+        } else {
+            self.continue_with(UnsavedAction::Open, ctx);
+        }
+    }
+
+    /// Does what the unsaved changes prompt held back.
+    fn continue_with(&mut self, action: UnsavedAction, ctx: &Context) {
+        match action {
+            UnsavedAction::Open => {
+                self.file_dialog.select_file();
+            }
+            UnsavedAction::CloseTab => self.closing = true,
+            UnsavedAction::Quit => {
+                self.close_confirmed = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    /// Asks whether to save the code before opening another file, closing the tab or quitting.
+    fn unsaved_window(&mut self, ctx: &Context) {
+        let Some(action) = self.unsaved_prompt else {
+            return;
+        };
+        egui::Window::new("Unsaved changes")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .resizable(false)
+            .collapsible(false)
+            .auto_sized()
+            .show(ctx, |ui| {
+                ui.label("The code has unsaved changes. Save them first?");
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.unsaved_prompt = None;
+                        if self.path.is_empty() {
+                            self.after_save = Some(action);
+                            self.save_as();
+                        } else if self.save_code() {
+                            self.continue_with(action, ctx);
                         }
                     }
-                    Err(_e) => {}
+                    if ui.button("Don't Save").clicked() {
+                        self.unsaved_prompt = None;
+                        self.continue_with(action, ctx);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.unsaved_prompt = None;
+                    }
+                });
+            });
+    }
+
+    /// Run, debugger and file buttons, shown above the code and below the settings.
+    fn controls(&mut self, ui: &mut egui::Ui, ctx: &Context) {
+        let running = *self.timer_running.lock().unwrap();
+        let paused = self.is_paused();
+
+        ui.add_enabled_ui(!running, |ui| {
+            if ui.button("Run").clicked() {
+                self.start_interpreter();
+            };
+        });
+
+        if paused {
+            if ui.button("Resume").clicked() {
+                self.resume_interpreter();
+            }
+        } else {
+            ui.add_enabled_ui(running, |ui| {
+                if ui.button("Pause").clicked() {
+                    self.pause_interpreter();
                 }
+            });
+        }
+
+        ui.add_enabled_ui(running || paused, |ui| {
+            if ui.button("Stop").clicked() {
+                self.stop_interpreter();
+            };
+        });
+
+        ui.add_enabled_ui(!running, |ui| {
+            if ui.button("Step").on_hover_text("Run one instruction").clicked() {
+                self.step_interpreter();
             }
+            if ui
+                .button("Step Over")
+                .on_hover_text("Run the loop starting here until it exits")
+                .clicked()
+            {
+                self.step_over_interpreter();
+            }
+            ui.add_enabled_ui(self.can_step_back(), |ui| {
+                if ui
+                    .button("Step Back")
+                    .on_hover_text("Undo the last instruction")
+                    .clicked()
+                {
+                    self.step_back_interpreter();
+                }
+                if ui
+                    .button("Run Back")
+                    .on_hover_text("Undo instructions until the previous breakpoint")
+                    .clicked()
+                {
+                    self.run_back_interpreter();
+                }
+            });
+            let cursor = egui::TextEdit::load_state(ctx, self.editor_id)
+                .and_then(|state| state.cursor.char_range())
+                .map(|range| range.primary.index);
+            ui.add_enabled_ui(cursor.is_some(), |ui| {
+                if ui
+                    .button("Run to Cursor")
+                    .on_hover_text("Run until the instruction at the cursor in the code")
+                    .clicked()
+                {
+                    self.run_to_position(cursor.unwrap_or_default());
+                }
+            });
+        });
 
-            #[cfg(target_os = "linux")]
+        ui.add_enabled_ui(!running && !paused, |ui| {
+            if ui.button("Select File").clicked() {
+                self.open_file(ctx);
+            }
+            if ui.button("Clear").clicked() {
+                self.input_brainfuck = Arc::new(Mutex::new("".to_string()));
+                *self.data.lock().unwrap() = vec![0; self.memory_size];
+                self.output.lock().unwrap().clear();
+                self.output_bytes.lock().unwrap().clear();
+                *self.box_index.lock().unwrap() = 0;
+                *self.tape_origin.lock().unwrap() = 0;
+                *self.machine.lock().unwrap() = None;
+                *self.profile.lock().unwrap() = None;
+                self.coverage = None;
+            }
+            if ui
+                .button("Generate from text")
+                .on_hover_text("Replace the code with a program that prints some text")
+                .clicked()
+            {
+                self.generator_open = true;
+            }
+            if ui
+                .button("Clear letters")
+                .on_hover_text("Remove comments and everything else that is not a command")
+                .clicked()
             {
-                self.path = path
-                    .to_str()
-                    .unwrap_or_else(|| "Error: Invalid path")
-                    .to_string();
-                match fs::read_to_string(self.path.clone()) {
-                    Ok(content) => {
-                        // Filter symbols
-                        let filtered: String = content
-                            .chars()
-                            .filter(|c| {
-                                ['[', ']', '-', '>', '+', '<', '.', ','].contains(c)
-                            })
-                            .collect();
-                        if self.counter > 0 {
-                            self.input_brainfuck = Arc::new(Mutex::new(filtered));
-                            self.counter -= 1;
+                let filtered: String = self
+                    .input_brainfuck
+                    .lock()
+                    .unwrap()
+                    .clone()
+                    .chars()
+                    .filter(|c| ['[', ']', '-', '>', '+', '<', '.', ','].contains(c))
+                    .collect();
+                self.input_brainfuck = Arc::new(Mutex::new(filtered));
+            }
+        });
+
+        self.set_path_multi(ctx);
+    }
+
+    /// The "Generate from text" window. Generating closes it.
+    fn generator_window(&mut self, ctx: &Context) {
+        let mut open = self.generator_open;
+        let mut generated = false;
+        egui::Window::new("Generate from text")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("A program printing this text replaces the code:");
+                ui.text_edit_multiline(&mut self.generator_text);
+                if ui.button("Generate").clicked() {
+                    let program = text_to_brainfuck(&self.generator_text, self.cell_width);
+                    *self.input_brainfuck.lock().unwrap() = program;
+                    generated = true;
+                }
+            });
+        self.generator_open = open && !generated;
+    }
+
+    /// The break condition and the watchpoints. They apply from the next Run or Resume.
+    fn break_settings(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Break when")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Condition");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.break_condition)
+                            .hint_text("cell[12] == 0 && ptr > 40")
+                            .font(egui::TextStyle::Monospace),
+                    )
+                    .on_hover_text(
+                        "Pause when this becomes true. Use ptr, cell, cell[i], steps, \
+                         numbers, + - * / %, comparisons, && || and !",
+                    );
+                });
+
+                let mut removed = None;
+                for (i, watch) in self.watchpoints.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label("Cell");
+                        ui.add(egui::DragValue::new(&mut watch.cell));
+                        egui::ComboBox::from_id_salt(("watch_kind", i))
+                            .selected_text(watch.kind.to_string())
+                            .show_ui(ui, |ui| {
+                                for kind in WatchKind::ALL {
+                                    ui.selectable_value(&mut watch.kind, kind, kind.to_string());
+                                }
+                            });
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
                         }
+                    });
+                }
+                if let Some(i) = removed {
+                    self.watchpoints.remove(i);
+                }
+
+                if ui
+                    .button("Add watchpoint")
+                    .on_hover_text("Watch the current cell. Right-clicking a cell also works")
+                    .clicked()
+                {
+                    let cell = *self.box_index.lock().unwrap() as isize
+                        - *self.tape_origin.lock().unwrap() as isize;
+                    self.watchpoints.push(Watchpoint {
+                        cell,
+                        kind: WatchKind::Change,
+                    });
+                }
+            });
+    }
+
+    /// Coverage of the finished runs plus the current one.
+    pub(crate) fn current_coverage(&self) -> Option<Coverage> {
+        if !self.coverage_enabled {
+            return None;
+        }
+        let mut coverage = self.coverage.clone()?;
+        if let Some(profile) = self.profile.lock().unwrap().as_ref() {
+            coverage.add(profile);
+        }
+        Some(coverage)
+    }
+
+    /// How much of the code and of its loop bodies ran, with a button to start over.
+    fn coverage_summary(&mut self, ui: &mut egui::Ui) {
+        let Some(coverage) = self.current_coverage() else {
+            return;
+        };
+        egui::CollapsingHeader::new("Coverage")
+            .default_open(false)
+            .show(ui, |ui| {
+                let commands: Vec<u64> = coverage
+                    .source()
+                    .chars()
+                    .zip(coverage.character_hits())
+                    .filter(|(c, _)| "+-<>[].,".contains(*c))
+                    .map(|(_, &hits)| hits)
+                    .collect();
+                let covered = commands.iter().filter(|&&hits| hits > 0).count();
+                let loops = coverage.loops();
+                let bodies = loops.iter().filter(|l| l.iterations > 0).count();
+                ui.label(format!("Runs: {}", coverage.runs()));
+                ui.label(format!("Commands: {covered} of {} ran", commands.len()));
+                ui.label(format!("Loop bodies: {bodies} of {} ran", loops.len()));
+                if ui.button("Reset coverage").clicked() {
+                    self.coverage = Some(Coverage::new(coverage.source()));
+                    *self.profile.lock().unwrap() = None;
+                }
+            });
+    }
+
+    /// Totals and the busiest loops of the last profiled run.
+    fn profile_summary(&mut self, ui: &mut egui::Ui) {
+        if !self.profiling {
+            return;
+        }
+        let Some(profile) = self.profile.lock().unwrap().clone() else {
+            return;
+        };
+        egui::CollapsingHeader::new("Profile")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.label(format!("Instructions executed: {}", profile.total_instructions()));
+                ui.label(format!("Highest cell: {}", profile.max_cell()));
+                let mut loops = profile.loops();
+                loops.sort_by_key(|l| std::cmp::Reverse(l.iterations));
+                for l in loops.iter().take(PROFILE_LOOPS_SHOWN) {
+                    ui.label(format!(
+                        "Loop {}..{}: {} iterations in {} entries",
+                        l.start, l.end, l.iterations, l.entries
+                    ));
+                }
+            });
+    }
+
+    /// The code editor. While a run is going or paused the code is read-only and the
+    /// next instruction is highlighted, but the cursor can still be placed.
+    /// Right-clicking a character toggles a breakpoint on it.
+    fn code_view(&mut self, ui: &mut egui::Ui, scroll_to_pointer: bool) {
+        let available_size = Vec2::new(ui.available_width(), 0.0);
+        let font_id = egui::FontId::new(14.0, egui::FontFamily::Monospace);
+        let running = *self.timer_running.lock().unwrap();
+        let session = running || self.is_paused();
+        let current = session.then(|| *self.letter_index.lock().unwrap());
+        let breakpoints = self.current_breakpoints();
+        let source_len = self.input_brainfuck.lock().unwrap().chars().count();
+        let heat = self
+            .profile
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|_| self.profiling)
+            .map(|profile| profile.character_hits(source_len));
+        let coverage = self.current_coverage();
+        // Coverage of code that was edited since is meaningless
+        let coverage = coverage
+            .as_ref()
+            .filter(|coverage| coverage.character_hits().len() == source_len)
+            .map(|coverage| coverage.character_hits());
+        let cursor = egui::TextEdit::load_state(ui.ctx(), self.editor_id)
+            .and_then(|state| state.cursor.char_range())
+            .map(|range| range.primary.index);
+        let dumps = self.debug_dumps;
+
+        let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+            let marks = CodeMarks {
+                current,
+                breakpoints: &breakpoints,
+                heat: heat.as_deref(),
+                coverage,
+                cursor,
+                dumps,
+            };
+            let mut job = layout_code(text, &marks, font_id.clone(), ui);
+            job.wrap.max_width = wrap_width;
+            ui.fonts(|fonts| fonts.layout_job(job))
+        };
+        // The code scrolls on its own, so it and the memory can both follow the pointer
+        egui::ScrollArea::vertical()
+            .id_salt(self.editor_id.with("scroll"))
+            .max_height(CODE_VIEW_HEIGHT)
+            .show(ui, |ui| {
+                let output = if session {
+                    let text = self.input_brainfuck.lock().unwrap().clone();
+                    egui::TextEdit::multiline(&mut text.as_str())
+                        .id(self.editor_id)
+                        .layouter(&mut layouter)
+                        .min_size(available_size)
+                        .show(ui)
+                } else {
+                    egui::TextEdit::multiline(&mut *self.input_brainfuck.lock().unwrap())
+                        .id(self.editor_id)
+                        .hint_text("Type brainfuck here...")
+                        .layouter(&mut layouter)
+                        .min_size(available_size)
+                        .show(ui)
+                };
+
+                if output.response.secondary_clicked() {
+                    if let Some(pointer) = output.response.interact_pointer_pos() {
+                        let cursor = output.galley.cursor_from_pos(pointer - output.galley_pos);
+                        self.toggle_breakpoint(cursor.ccursor.index);
                     }
-                    Err(_e) => {}
                 }
-            }
+
+                // Keep the next instruction visible while the program runs
+                if let Some(index) = current.filter(|_| running || scroll_to_pointer) {
+                    let cursor = output.galley.from_ccursor(CCursor::new(index));
+                    let rect = output.galley.pos_from_cursor(&cursor);
+                    ui.scroll_to_rect(rect.translate(output.galley_pos.to_vec2()), None);
+                }
+            });
+
+        let code = self.input_brainfuck.lock().unwrap().clone();
+        let partners = bracket_partners(&code);
+        let unmatched = code
+            .chars()
+            .zip(&partners)
+            .position(|(c, partner)| matches!(c, '[' | ']') && partner.is_none());
+        if let Some(position) = unmatched {
+            let bracket = code.chars().nth(position).unwrap_or_default();
+            ui.colored_label(
+                Color32::RED,
+                format!("Unmatched {bracket} at character {position}, underlined in the code"),
+            );
         }
     }
 }
 
-impl eframe::App for BrainfuckInterpreterInterface {
+impl eframe::App for BrainfuckInterpreterApp {
+    /// Called by the framework to save state before shutdown.
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, self);
+    }
+
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.theme = ctx.options(|options| options.theme_preference);
+        if ctx.input(|i| i.viewport().close_requested()) {
+            // Each tab with unsaved changes asks in turn, the next close request comes
+            // from the prompt of the one before
+            let dirty = self
+                .tabs
+                .iter()
+                .position(|tab| tab.is_dirty() && !tab.close_confirmed);
+            if let Some(index) = dirty {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.active = index;
+                self.tabs[index].unsaved_prompt = Some(UnsavedAction::Quit);
+            }
+        }
+        // Shift+S would also match the plain shortcut, so check it first
+        if ctx.input_mut(|i| i.consume_shortcut(&SAVE_AS_SHORTCUT)) {
+            self.tabs[self.active].save_as();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&SAVE_SHORTCUT)) {
+            self.tabs[self.active].save_code();
+        }
+        let title = self.tabs[self.active].window_title();
+        if title != self.title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.button("New Tab").clicked() {
+                        self.new_tab();
+                        ui.close_menu();
+                    }
+                    let save = egui::Button::new("Save").shortcut_text(ctx.format_shortcut(&SAVE_SHORTCUT));
+                    if ui.add(save).clicked() {
+                        self.tabs[self.active].save_code();
+                        ui.close_menu();
+                    }
+                    let save_as = egui::Button::new("Save As…")
+                        .shortcut_text(ctx.format_shortcut(&SAVE_AS_SHORTCUT));
+                    if ui.add(save_as).clicked() {
+                        self.tabs[self.active].save_as();
+                        ui.close_menu();
+                    }
+                    if ui.button("Close Tab").clicked() {
+                        self.close_tab(self.active);
+                        ui.close_menu();
+                    }
+                    ui.separator();
                     if ui.button("Quit").clicked() {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
@@ -125,102 +914,37 @@ impl eframe::App for BrainfuckInterpreterInterface {
 
                 egui::widgets::global_theme_preference_buttons(ui);
             });
+            self.tab_bar(ui);
         });
 
+        self.tabs[self.active].show(ctx);
+        self.remove_closed_tabs();
+        // Request a repaint to keep the animation going, and the other tabs running
+        ctx.request_repaint();
+    }
+}
+
+impl BrainfuckInterpreterInterface {
+    /// The code, controls, settings and memory of this tab.
+    fn show(&mut self, ctx: &Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::ScrollArea::vertical()
                 .auto_shrink([false; 2]) // Prevent auto-shrinking of the scroll area
                 .show(ui, |ui| {
-                    ui.horizontal(|ui| {
+                    ui.horizontal_wrapped(|ui| {
                         ui.heading("Brainfuck code");
-
-                        let not_running = !*self.timer_running.lock().unwrap();
-
-                        ui.add_enabled_ui(not_running, |ui| {
-                            if ui.button("Run").clicked() {
-                                self.start_interpreter();
-                            };
-                        });
-
-                        ui.add_enabled_ui(!not_running, |ui| {
-                            if ui.button("Stop").clicked() && *self.timer_running.lock().unwrap() {
-                                self.stop_interpreter();
-                            };
-                        });
-
-                        ui.add_enabled_ui(not_running, |ui| {
-                            if ui.button("Select File").clicked() {
-                                self.file_dialog.select_file();
-                                self.counter += 200;
-                            }
-                            if ui.button("Clear").clicked() {
-                                self.input_brainfuck = Arc::new(Mutex::new("".to_string()));
-                                self.data.lock().unwrap().fill(0);
-                                self.output.lock().unwrap().clear();
-                                *self.box_index.lock().unwrap() = 0;
-                            }
-                            if ui.button("Clear letters").clicked() {
-                                let filtered: String = self
-                                    .input_brainfuck
-                                    .lock()
-                                    .unwrap()
-                                    .clone()
-                                    .chars()
-                                    .filter(|c| {
-                                        ['[', ']', '-', '>', '+', '<', '.', ','].contains(c)
-                                    })
-                                    .collect();
-                                self.input_brainfuck = Arc::new(Mutex::new(filtered));
-                            }
-                        });
-
-                        self.set_path_multi(ctx);
+                        self.controls(ui, ctx);
                     });
 
-                    let available_size = Vec2::new(ui.available_width(), 0.0);
-
-                    if !*self.timer_running.lock().unwrap() {
-                        ui.add_sized(
-                            available_size,
-                            egui::TextEdit::multiline(&mut *self.input_brainfuck.lock().unwrap())
-                                .hint_text("Type brainfuck here...")
-                                .interactive(!*self.timer_running.lock().unwrap())
-                                .font(egui::FontId::new(14.0, egui::FontFamily::Monospace)),
-                        );
-                    } else {
-                        let letter_index = *self.letter_index.lock().unwrap(); //TODO: fix lag on big delay
-                        let input_brainfuck = self.input_brainfuck.lock().unwrap();
-                        let text = &*input_brainfuck;
-
-                        let start_idx = text
-                            .char_indices()
-                            .nth(letter_index)
-                            .map(|(idx, _)| idx)
-                            .unwrap_or(text.len());
-                        let end_idx = text
-                            .char_indices()
-                            .nth(letter_index + 1)
-                            .map(|(idx, _)| idx)
-                            .unwrap_or(text.len());
-
-                        // Split the text
-                        let before = &text[..start_idx];
-                        let highlighted = &text[start_idx..end_idx];
-                        let after = &text[end_idx..];
-
-                        // Create RichText for each segment
-                        let before_text = egui::RichText::new(before);
-                        let highlighted_text =
-                            egui::RichText::new(highlighted).background_color(Color32::RED);
-                        let after_text = egui::RichText::new(after);
-
-                        ui.horizontal_wrapped(|ui| {
-                            ui.label(before_text);
-                            ui.label(highlighted_text);
-                            ui.label(after_text);
-                        });
+                    let reason = self.pause_reason.lock().unwrap().clone();
+                    if !reason.is_empty() && self.is_paused() {
+                        ui.colored_label(Color32::GOLD, format!("Paused: {reason}"));
                     }
 
+                    let scroll_to_pointer =
+                        std::mem::take(&mut *self.scroll_to_pointer.lock().unwrap());
+                    self.code_view(ui, scroll_to_pointer);
+
                     ui.add_space(10.0);
                     let box_size = 30.0;
                     let highlight_color = Color32::RED;
@@ -245,22 +969,83 @@ impl eframe::App for BrainfuckInterpreterInterface {
                                         .text("Delay"),
                                 );
                                 ui.horizontal(|ui| {
-                                    ui.label(format!(
-                                        "Memory size: {}",
-                                        self.data.lock().unwrap().len()
-                                    ));
+                                    egui::ComboBox::from_label("Cell width")
+                                        .selected_text(self.cell_width.to_string())
+                                        .show_ui(ui, |ui| {
+                                            for width in CellWidth::ALL {
+                                                ui.selectable_value(
+                                                    &mut self.cell_width,
+                                                    width,
+                                                    width.to_string(),
+                                                );
+                                            }
+                                        });
+                                    egui::ComboBox::from_label("Overflow")
+                                        .selected_text(self.overflow_policy.to_string())
+                                        .show_ui(ui, |ui| {
+                                            for policy in OverflowPolicy::ALL {
+                                                ui.selectable_value(
+                                                    &mut self.overflow_policy,
+                                                    policy,
+                                                    policy.to_string(),
+                                                );
+                                            }
+                                        });
+                                });
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_label("Tape")
+                                        .selected_text(self.tape_mode.to_string())
+                                        .show_ui(ui, |ui| {
+                                            for mode in TapeMode::ALL {
+                                                ui.selectable_value(
+                                                    &mut self.tape_mode,
+                                                    mode,
+                                                    mode.to_string(),
+                                                );
+                                            }
+                                        });
+                                    egui::ComboBox::from_label("End of input")
+                                        .selected_text(self.eof_behavior.to_string())
+                                        .show_ui(ui, |ui| {
+                                            for behavior in EofBehavior::ALL {
+                                                ui.selectable_value(
+                                                    &mut self.eof_behavior,
+                                                    behavior,
+                                                    behavior.to_string(),
+                                                );
+                                            }
+                                        })
+                                        .response
+                                        .on_hover_text(
+                                            "What `,` does when the input box is empty",
+                                        );
+                                    ui.checkbox(&mut self.optimize, "Optimize")
+                                        .on_hover_text("Fold runs and simplify common loops");
+                                    ui.checkbox(&mut self.debug_dumps, "# breaks")
+                                        .on_hover_text("Pause at every `#` in the code");
+                                    if cfg!(feature = "jit") {
+                                        ui.checkbox(&mut self.jit, "JIT").on_hover_text(
+                                            "Run as x86-64 machine code. Runs cannot be stepped back, and profiling turns it off",
+                                        );
+                                    }
+                                    ui.checkbox(&mut self.profiling, "Profile").on_hover_text(
+                                        "Count how often each instruction runs and shade the code by it",
+                                    );
+                                    ui.checkbox(&mut self.coverage_enabled, "Coverage").on_hover_text(
+                                        "Color the commands that ran in green and the ones that never ran in red, over all runs",
+                                    );
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label(format!("Memory size: {}", self.memory_size));
                                     if ui.button("+").clicked() {
-                                        let data_len: i64 = self.data.lock().unwrap().len() as i64;
-                                        let new_size: i64 =
-                                            data_len + 2usize.pow(self.power) as i64;
-                                        self.data.lock().unwrap().resize(new_size as usize, 0);
+                                        self.memory_size += 2usize.pow(self.power);
+                                        self.data.lock().unwrap().resize(self.memory_size, 0);
                                     }
                                     if ui.button("-").clicked() {
-                                        let data_len: i64 = self.data.lock().unwrap().len() as i64;
-                                        let mut new_size: i64 =
-                                            data_len - 2usize.pow(self.power) as i64;
-                                        new_size = new_size.max(2);
-                                        self.data.lock().unwrap().resize(new_size as usize, 0);
+                                        let step = 2usize.pow(self.power);
+                                        self.memory_size =
+                                            self.memory_size.saturating_sub(step).max(2);
+                                        self.data.lock().unwrap().resize(self.memory_size, 0);
                                     }
                                     ui.style_mut().spacing.slider_width = 51.0;
                                     ui.add(
@@ -269,52 +1054,14 @@ impl eframe::App for BrainfuckInterpreterInterface {
                                 })
                             });
 
-                            ui.add_space(10.0);
-
-                            ui.horizontal(|ui| {
-                                let not_running = !*self.timer_running.lock().unwrap();
-
-                                ui.add_enabled_ui(not_running, |ui| {
-                                    if ui.button("Run").clicked() {
-                                        self.start_interpreter();
-                                    };
-                                });
+                            self.break_settings(ui);
+                            self.profile_summary(ui);
+                            self.coverage_summary(ui);
 
-                                ui.add_enabled_ui(!not_running, |ui| {
-                                    if ui.button("Stop").clicked()
-                                        && *self.timer_running.lock().unwrap()
-                                    {
-                                        self.stop_interpreter();
-                                    };
-                                });
-
-                                ui.add_enabled_ui(not_running, |ui| {
-                                    if ui.button("Select File").clicked() {
-                                        self.file_dialog.select_file();
-                                        self.counter += 200;
-                                    }
-                                    if ui.button("Clear").clicked() {
-                                        self.input_brainfuck = Arc::new(Mutex::new("".to_string()));
-                                        self.data.lock().unwrap().fill(0);
-                                        self.output.lock().unwrap().clear();
-                                        *self.box_index.lock().unwrap() = 0;
-                                    }
-                                    if ui.button("Clear letters").clicked() {
-                                        let filtered: String = self
-                                            .input_brainfuck
-                                            .lock()
-                                            .unwrap()
-                                            .clone()
-                                            .chars()
-                                            .filter(|c| {
-                                                ['[', ']', '-', '>', '+', '<', '.', ','].contains(c)
-                                            })
-                                            .collect();
-                                        self.input_brainfuck = Arc::new(Mutex::new(filtered));
-                                    }
-                                });
+                            ui.add_space(10.0);
 
-                                self.set_path_multi(ctx);
+                            ui.horizontal_wrapped(|ui| {
+                                self.controls(ui, ctx);
                             });
                         });
 
@@ -326,14 +1073,21 @@ impl eframe::App for BrainfuckInterpreterInterface {
                             ui.horizontal_wrapped(|ui| {
                                 let clip_rect = ui.clip_rect(); // Get the visible area
                                 let data = self.data.lock().unwrap(); // Lock the data for access
+                                let origin = *self.tape_origin.lock().unwrap() as isize;
+                                let show_indices = self.tape_mode == TapeMode::Bidirectional;
+                                let mut toggled_watch = None;
 
                                 for (i, value) in data.iter().enumerate() {
                                     // Allocate space for the current box and get its rectangle
-                                    let (_, rect) = ui.allocate_space([box_size, box_size].into());
+                                    let (id, rect) = ui.allocate_space([box_size, box_size].into());
+                                    let current = i == *self.box_index.lock().unwrap();
+                                    if current && scroll_to_pointer {
+                                        ui.scroll_to_rect(rect, Some(egui::Align::Center));
+                                    }
 
                                     // Check if the box is within the visible area
                                     if rect.intersects(clip_rect) {
-                                        let rect_color = if i == *self.box_index.lock().unwrap() {
+                                        let rect_color = if current {
                                             highlight_color
                                         } else {
                                             if ctx.style().visuals.dark_mode {
@@ -350,7 +1104,7 @@ impl eframe::App for BrainfuckInterpreterInterface {
                                         ui.painter().text(
                                             rect.center(),
                                             egui::Align2::CENTER_CENTER,
-                                            format!("{}", value),
+                                            format_cell(*value),
                                             egui::TextStyle::Body.resolve(ui.style()),
                                             if ctx.style().visuals.dark_mode {
                                                 Color32::GRAY
@@ -358,8 +1112,44 @@ impl eframe::App for BrainfuckInterpreterInterface {
                                                 Color32::WHITE
                                             },
                                         );
+
+                                        // Cells left of the start have negative indices
+                                        let index = i as isize - origin;
+                                        if show_indices {
+                                            ui.painter().text(
+                                                rect.left_top() + egui::vec2(2.0, 1.0),
+                                                egui::Align2::LEFT_TOP,
+                                                index.to_string(),
+                                                egui::FontId::proportional(8.0),
+                                                Color32::LIGHT_GRAY,
+                                            );
+                                        }
+
+                                        let watched = self.watchpoints.iter().any(|w| w.cell == index);
+                                        if watched {
+                                            ui.painter().rect_stroke(
+                                                rect,
+                                                1.2,
+                                                egui::Stroke::new(2.0, Color32::GOLD),
+                                            );
+                                        }
+
+                                        // Large values are abbreviated, show the exact one on hover
+                                        let response = ui
+                                            .interact(rect, id, egui::Sense::click())
+                                            .on_hover_text(format!(
+                                                "Cell {index}: {value}\nRight-click to watch for changes"
+                                            ));
+                                        if response.secondary_clicked() {
+                                            toggled_watch = Some(index);
+                                        }
                                     }
                                 }
+                                drop(data);
+
+                                if let Some(cell) = toggled_watch {
+                                    self.toggle_watchpoint(cell);
+                                }
                             });
                         });
                     });
@@ -369,6 +1159,9 @@ impl eframe::App for BrainfuckInterpreterInterface {
                 egui::warn_if_debug_build(ui);
             });
 
+            self.generator_window(ctx);
+            self.unsaved_window(ctx);
+
             if *self.warn.lock().unwrap() {
                 egui::Window::new("Warning")
                     .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
@@ -391,11 +1184,158 @@ impl eframe::App for BrainfuckInterpreterInterface {
                     });
             }
         });
-        // Request a repaint to keep the animation going
-        ctx.request_repaint();
     }
 }
 
+/// What the code view marks on top of the plain text.
+struct CodeMarks<'a> {
+    /// Character index of the next instruction.
+    current: Option<usize>,
+    breakpoints: &'a BTreeSet<usize>,
+    /// How often each character ran, from the profiler.
+    heat: Option<&'a [u64]>,
+    /// How often each character ran over all runs, for the coverage colors.
+    coverage: Option<&'a [u64]>,
+    /// Character index of the text cursor, to mark the bracket pair next to it.
+    cursor: Option<usize>,
+    /// Whether `#` is a command rather than a comment.
+    dumps: bool,
+}
+
+/// Lays out the code colored by the kind of each command, with comments dimmed,
+/// the bracket pair at the cursor marked and unmatched brackets underlined in red.
+/// On top of that the next instruction is on a red background, breakpoints on a
+/// gold one and, when profiling, commands are shaded by how often they ran.
+/// With coverage on, commands that ran are green and the others red.
+fn layout_code(text: &str, marks: &CodeMarks<'_>, font_id: egui::FontId, ui: &egui::Ui) -> LayoutJob {
+    let visuals = ui.visuals();
+    let normal = TextFormat::simple(font_id, visuals.text_color());
+    let max_hits = marks.heat.and_then(|heat| heat.iter().max().copied()).unwrap_or(0);
+    let partners = bracket_partners(text);
+    let pair_at = |index: usize| partners.get(index).copied().flatten().map(|partner| (index, partner));
+    // The bracket after the cursor wins over the one before it
+    let pair = marks
+        .cursor
+        .and_then(|cursor| pair_at(cursor).or_else(|| pair_at(cursor.checked_sub(1)?)));
+    let format_of = |index: usize, c: char| {
+        let unmatched = matches!(c, '[' | ']') && partners[index].is_none();
+        let underline = if unmatched {
+            egui::Stroke::new(2.0, Color32::RED)
+        } else {
+            egui::Stroke::NONE
+        };
+        let background = if Some(index) == marks.current {
+            Color32::RED
+        } else if marks.breakpoints.contains(&index) {
+            return TextFormat {
+                background: Color32::GOLD,
+                color: Color32::BLACK,
+                underline,
+                ..normal.clone()
+            };
+        } else if pair.is_some_and(|(a, b)| index == a || index == b) {
+            visuals.widgets.hovered.bg_fill
+        } else {
+            let hits = marks.heat.and_then(|heat| heat.get(index)).copied().unwrap_or(0);
+            if "+-<>[].,#".contains(c) {
+                heat_color(hits, max_hits)
+            } else {
+                Color32::TRANSPARENT
+            }
+        };
+        let color = match marks.coverage.and_then(|hits| hits.get(index)) {
+            _ if unmatched => Color32::RED,
+            Some(0) if "+-<>[].,".contains(c) => Color32::RED,
+            Some(_) if "+-<>[].,".contains(c) => Color32::GREEN,
+            _ => match c {
+                '<' | '>' => MOVE_COLOR,
+                '+' | '-' => ARITHMETIC_COLOR,
+                '.' | ',' => IO_COLOR,
+                '[' | ']' => visuals.strong_text_color(),
+                '#' if marks.dumps => visuals.warn_fg_color,
+                _ => visuals.weak_text_color(),
+            },
+        };
+        TextFormat {
+            background,
+            color,
+            underline,
+            ..normal.clone()
+        }
+    };
+
+    // Consecutive characters with the same look share a section
+    let mut job = LayoutJob::default();
+    let mut section: Option<(usize, TextFormat)> = None;
+    for (index, (byte, c)) in text.char_indices().enumerate() {
+        let format = format_of(index, c);
+        if section.as_ref().is_some_and(|(_, current)| *current == format) {
+            continue;
+        }
+        if let Some((start, format)) = section.take() {
+            job.append(&text[start..byte], 0.0, format);
+        }
+        section = Some((byte, format));
+    }
+    if let Some((start, format)) = section {
+        job.append(&text[start..], 0.0, format);
+    }
+    job
+}
+
+/// For every character of `text`, the index of the bracket it pairs with. `None`
+/// for characters that are not brackets and for brackets without a partner.
+pub(crate) fn bracket_partners(text: &str) -> Vec<Option<usize>> {
+    let mut partners = vec![None; text.chars().count()];
+    let mut open = Vec::new();
+    for (index, c) in text.chars().enumerate() {
+        match c {
+            '[' => open.push(index),
+            ']' => {
+                if let Some(start) = open.pop() {
+                    partners[start] = Some(index);
+                    partners[index] = Some(start);
+                }
+            }
+            _ => {}
+        }
+    }
+    partners
+}
+
+/// Background for code that ran `hits` times out of at most `max_hits`, on a log
+/// scale from faint yellow to strong red.
+fn heat_color(hits: u64, max_hits: u64) -> Color32 {
+    if hits == 0 {
+        return Color32::TRANSPARENT;
+    }
+    let heat = ((hits as f32).ln_1p() / (max_hits as f32).ln_1p()).clamp(0.0, 1.0);
+    Color32::from_rgba_unmultiplied(255, (200.0 * (1.0 - heat)) as u8, 0, (40.0 + 120.0 * heat) as u8)
+}
+
+/// Formats a cell value so it fits in a memory box, e.g. `65535` as `65k`.
+fn format_cell(value: u64) -> String {
+    const SUFFIXES: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+    if value < 1000 {
+        return value.to_string();
+    }
+    let mut scaled = value / 1000;
+    let mut suffix = 0;
+    while scaled >= 1000 {
+        scaled /= 1000;
+        suffix += 1;
+    }
+    format!("{scaled}{}", SUFFIXES[suffix])
+}
+
+/// The picked path as a string. On Windows the dialog returns `\\?\` paths.
+fn dialog_path(path: &Path) -> String {
+    let path = path.to_str().unwrap_or("Error: Invalid path");
+    #[cfg(target_os = "windows")]
+    let path = &path[4..];
+    path.to_string()
+}
+
 fn powered_by_egui_and_eframe(ui: &mut egui::Ui) {
     ui.horizontal(|ui| {
         ui.spacing_mut().item_spacing.x = 0.0;
@@ -0,0 +1,418 @@
+#![warn(clippy::all, rust_2018_idioms)]
+//! Headless Brainfuck runner: `bf [OPTIONS] <FILE>`.
+//!
+//! Program input is read from stdin and program output is written to stdout.
+//! `bf build <FILE> -o <OUTPUT>` compiles the program to a Linux executable instead.
+
+use brain_fuck_interpreter::{
+    to_c, to_elf, to_rust, to_wasm, to_wat, CellWidth, Coverage, EofBehavior, Machine,
+    MachineError, Optimizations, OverflowPolicy, Program, Status, TapeMode, TranspileOptions,
+};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+const EXIT_RUNTIME_ERROR: u8 = 1;
+const EXIT_BRACKET_MISMATCH: u8 = 2;
+const EXIT_USAGE: u8 = 3;
+const EXIT_LIMIT_REACHED: u8 = 4;
+
+/// Instructions run between two writes to stdout.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+const USAGE: &str = "\
+Usage: bf [OPTIONS] <FILE>
+       bf build [OPTIONS] <FILE> [-o <OUTPUT>]
+
+Runs a Brainfuck program, reading its input from stdin and writing its output to stdout.
+`bf build` compiles it to a static Linux x86-64 executable instead, which takes the tape,
+cell width and EOF options. Its tape can grow to 2^28 cells, or --tape-size if larger.
+
+Options:
+      --tape-size <N>     Initial number of cells [default: 256]
+      --tape <MODE>       Tape shape: standard (grows right), bidirectional or circular
+                          [default: standard]
+      --cell-width <BITS> Cell width in bits: 8, 16, 32 or 64 [default: 8]
+      --overflow <POLICY> What happens when a cell goes past its bounds: wrap, saturate, trap
+                          [default: wrap]
+      --eof <MODE>        What `,` does at end of input: unchanged, zero, minus-one,
+                          block (wait for more, fail at the end of stdin) or error
+                          [default: unchanged]
+      --limit <N>         Stop after executing N instructions
+      --no-optimize       Run without the optimization passes
+      --jit               Compile the program to x86-64 machine code before running it.
+                          Needs the `jit` feature, otherwise the program is interpreted
+      --dump              Treat `#` as a debug instruction that prints the tape to stderr
+      --profile <FILE>    Write execution counts per instruction and loop to FILE
+      --profile-json <FILE>
+                          Like --profile, but as JSON
+      --lcov <FILE>       Add the lines and loop bodies this run covered to the lcov
+                          file FILE, creating it if needed. Loops are not optimized away
+      --emit <LANG>       Write the program as a standalone source file to stdout instead
+                          of running it, with the same tape, cell width and EOF options:
+                          c, rust (a main.rs without dependencies), wat or wasm (a
+                          module importing env.read_byte and env.write_byte and
+                          exporting run)
+  -o, --output <FILE>     Where `bf build` writes the executable [default: FILE without
+                          its extension]
+  -h, --help              Print this help
+
+Exit codes:
+  0  The program finished
+  1  Runtime error, such as moving left of the first cell
+  2  The brackets in the program do not match
+  3  Invalid arguments or the file could not be read
+  4  The instruction limit was reached";
+
+struct Options {
+    path: String,
+    tape_size: usize,
+    tape_mode: TapeMode,
+    cell_width: CellWidth,
+    overflow_policy: OverflowPolicy,
+    eof_behavior: EofBehavior,
+    limit: Option<u64>,
+    optimize: bool,
+    jit: bool,
+    dumps: bool,
+    profile_text: Option<String>,
+    profile_json: Option<String>,
+    lcov: Option<String>,
+    emit: Option<Language>,
+    /// Where `bf build` writes the executable, `None` when running the program.
+    build: Option<String>,
+}
+
+/// Languages `--emit` can translate a program to.
+#[derive(Clone, Copy)]
+enum Language {
+    C,
+    Rust,
+    Wat,
+    Wasm,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut options = Options {
+        path: String::new(),
+        tape_size: Machine::DEFAULT_TAPE_SIZE,
+        tape_mode: TapeMode::default(),
+        cell_width: CellWidth::default(),
+        overflow_policy: OverflowPolicy::default(),
+        eof_behavior: EofBehavior::default(),
+        limit: None,
+        optimize: true,
+        jit: false,
+        dumps: false,
+        profile_text: None,
+        profile_json: None,
+        lcov: None,
+        emit: None,
+        build: None,
+    };
+    let mut build = false;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "--tape-size" => {
+                options.tape_size = parse_number(&value(&arg)?, &arg)?;
+            }
+            "--tape" => {
+                options.tape_mode = match value(&arg)?.as_str() {
+                    "standard" => TapeMode::Standard,
+                    "bidirectional" => TapeMode::Bidirectional,
+                    "circular" => TapeMode::Circular,
+                    other => return Err(format!("unknown tape mode: {other}")),
+                }
+            }
+            "--cell-width" => {
+                let bits = value(&arg)?;
+                options.cell_width = parse_number(&bits, &arg)
+                    .ok()
+                    .and_then(CellWidth::from_bits)
+                    .ok_or_else(|| format!("unsupported cell width: {bits}"))?;
+            }
+            "--overflow" => {
+                options.overflow_policy = match value(&arg)?.as_str() {
+                    "wrap" => OverflowPolicy::Wrap,
+                    "saturate" => OverflowPolicy::Saturate,
+                    "trap" => OverflowPolicy::Trap,
+                    other => return Err(format!("unknown overflow policy: {other}")),
+                }
+            }
+            "--eof" => {
+                options.eof_behavior = match value(&arg)?.as_str() {
+                    "unchanged" => EofBehavior::Unchanged,
+                    "zero" => EofBehavior::Zero,
+                    "minus-one" => EofBehavior::MinusOne,
+                    "block" => EofBehavior::Block,
+                    "error" => EofBehavior::Error,
+                    other => return Err(format!("unknown EOF mode: {other}")),
+                }
+            }
+            "--limit" => options.limit = Some(parse_number(&value(&arg)?, &arg)?),
+            "--no-optimize" => options.optimize = false,
+            "--jit" => options.jit = true,
+            "--dump" => options.dumps = true,
+            "--profile" => options.profile_text = Some(value(&arg)?),
+            "--profile-json" => options.profile_json = Some(value(&arg)?),
+            "--lcov" => options.lcov = Some(value(&arg)?),
+            "--emit" => {
+                options.emit = match value(&arg)?.as_str() {
+                    "c" => Some(Language::C),
+                    "rust" => Some(Language::Rust),
+                    "wat" => Some(Language::Wat),
+                    "wasm" => Some(Language::Wasm),
+                    other => return Err(format!("unknown language: {other}")),
+                }
+            }
+            "build" if !build && path.is_none() => build = true,
+            "-o" | "--output" => output = Some(value(&arg)?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option: {arg}"))
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+
+    options.path = path.ok_or("missing program file")?;
+    if build {
+        if options.emit.is_some() {
+            return Err("bf build does not support --emit".to_string());
+        }
+        // Only the file name's extension is dropped, never a dot in a directory name
+        let path = Path::new(&options.path);
+        let default = match path.extension() {
+            Some(_) => path.with_extension("").to_string_lossy().into_owned(),
+            None => format!("{}.out", options.path),
+        };
+        options.build = Some(output.unwrap_or(default));
+    } else if output.is_some() {
+        return Err("-o is only used by bf build".to_string());
+    }
+    if options.emit.is_some() || options.build.is_some() {
+        // Generated programs always wrap and run to the end
+        let name = if build { "bf build" } else { "--emit" };
+        if options.overflow_policy != OverflowPolicy::Wrap {
+            return Err(format!("{name} only supports --overflow wrap"));
+        }
+        if options.limit.is_some() {
+            return Err(format!("{name} does not support --limit"));
+        }
+        // Nothing runs here to debug, profile or cover
+        let unsupported = [
+            ("--dump", options.dumps),
+            ("--jit", options.jit),
+            ("--profile", options.profile_text.is_some()),
+            ("--profile-json", options.profile_json.is_some()),
+            ("--lcov", options.lcov.is_some()),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, used)| *used) {
+            return Err(format!("{name} does not support {flag}"));
+        }
+    }
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {name}: {value}"))
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("bf: {e}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let source = match std::fs::read_to_string(&options.path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("bf: {}: {e}", options.path);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let optimizations = if !options.optimize {
+        Optimizations::none()
+    } else if options.lcov.is_some() {
+        Coverage::optimizations()
+    } else {
+        Optimizations::all()
+    };
+    let optimizations = optimizations
+        .for_overflow_policy(options.overflow_policy)
+        .for_tape_mode(options.tape_mode);
+    let program = if options.dumps {
+        Program::compile_with_dumps(&source, &optimizations)
+    } else {
+        Program::compile_with(&source, &optimizations)
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("bf: {e}");
+            return ExitCode::from(EXIT_BRACKET_MISMATCH);
+        }
+    };
+
+    let transpile_options = TranspileOptions {
+        tape_size: options.tape_size,
+        tape_mode: options.tape_mode,
+        cell_width: options.cell_width,
+        eof_behavior: options.eof_behavior,
+    };
+    if let Some(path) = &options.build {
+        if let Err(e) = write_executable(path, &to_elf(&program, &transpile_options)) {
+            eprintln!("bf: {path}: {e}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Some(language) = options.emit {
+        let code = match language {
+            Language::C => to_c(&program, &transpile_options).into_bytes(),
+            Language::Rust => to_rust(&program, &transpile_options).into_bytes(),
+            Language::Wat => to_wat(&program, &transpile_options).into_bytes(),
+            Language::Wasm => to_wasm(&program, &transpile_options),
+        };
+        if let Err(e) = io::stdout().write_all(&code) {
+            eprintln!("bf: {e}");
+            return ExitCode::from(EXIT_RUNTIME_ERROR);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let mut machine = Machine::from_program(program)
+        .with_tape_size(options.tape_size)
+        .with_tape_mode(options.tape_mode)
+        .with_cell_width(options.cell_width)
+        .with_overflow_policy(options.overflow_policy)
+        .with_eof_behavior(options.eof_behavior);
+    if options.profile_text.is_some() || options.profile_json.is_some() || options.lcov.is_some() {
+        machine = machine.with_profiling();
+    }
+    if options.jit {
+        machine = machine.with_jit();
+    }
+
+    let result = run(&mut machine, options.limit);
+
+    // The profile is written even if the program failed, it shows where it got to
+    if let Some(profile) = machine.profile() {
+        let reports = [
+            (&options.profile_text, profile.to_text()),
+            (&options.profile_json, profile.to_json()),
+        ];
+        for (path, report) in reports {
+            let Some(path) = path else { continue };
+            if let Err(e) = std::fs::write(path, report) {
+                eprintln!("bf: {path}: {e}");
+                return ExitCode::from(EXIT_USAGE);
+            }
+        }
+    }
+
+    if let (Some(path), Some(profile)) = (&options.lcov, machine.profile()) {
+        let mut coverage = Coverage::new(&source);
+        coverage.add(profile);
+        let previous = std::fs::read_to_string(path).unwrap_or_default();
+        if let Err(e) = std::fs::write(path, coverage.merge_into_lcov(&previous, &options.path)) {
+            eprintln!("bf: {path}: {e}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+
+    match result {
+        Ok(Status::Halted) => ExitCode::SUCCESS,
+        Ok(_) => {
+            eprintln!(
+                "bf: instruction limit of {} reached",
+                options.limit.unwrap_or_default()
+            );
+            ExitCode::from(EXIT_LIMIT_REACHED)
+        }
+        Err(RunError::Machine(e)) => {
+            match e.position() {
+                Some(position) => eprintln!("bf: {e} (at character {position})"),
+                None => eprintln!("bf: {e}"),
+            }
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+        Err(RunError::Io(e)) => {
+            eprintln!("bf: {e}");
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
+
+/// Writes `bytes` to `path` and makes it executable.
+fn write_executable(path: &str, bytes: &[u8]) -> io::Result<()> {
+    std::fs::write(path, bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+enum RunError {
+    Machine(MachineError),
+    Io(io::Error),
+}
+
+/// Runs until the program halts, fails or uses up `limit` instructions,
+/// feeding stdin to the machine whenever it asks for input.
+fn run(machine: &mut Machine, limit: Option<u64>) -> Result<Status, RunError> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let mut buffer = [0; 4096];
+
+    let status = loop {
+        let remaining = limit.map_or(CHUNK_SIZE, |limit| {
+            limit.saturating_sub(machine.instructions_executed())
+        });
+        if remaining == 0 {
+            break Status::Running;
+        }
+        let status = machine.run_for(remaining.min(CHUNK_SIZE) as usize);
+        let status = status.map_err(RunError::Machine);
+        stdout
+            .write_all(&machine.take_output())
+            .map_err(RunError::Io)?;
+        match status? {
+            Status::Running => {}
+            Status::AwaitingInput => {
+                stdout.flush().map_err(RunError::Io)?;
+                match stdin.read(&mut buffer).map_err(RunError::Io)? {
+                    0 => machine.close_input(),
+                    n => machine.push_input(&buffer[..n]),
+                }
+            }
+            Status::Dump => {
+                stdout.flush().map_err(RunError::Io)?;
+                eprintln!("#: {}", machine.dump());
+            }
+            Status::Halted => break Status::Halted,
+        }
+    };
+
+    stdout.flush().map_err(RunError::Io)?;
+    Ok(status)
+}
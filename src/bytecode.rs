@@ -0,0 +1,126 @@
+use crate::machine::MachineError;
+use crate::optimizer::{self, Optimizations};
+
+/// A single Brainfuck operation with its jump targets already resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `+` and `-`, possibly folded: add this amount to the current cell.
+    Add(i64),
+    /// `>` and `<`, possibly folded: move the data pointer by this many cells.
+    Move(isize),
+    Output,
+    Input,
+    /// `[`: jump to the instruction after the matching `]` if the cell is zero.
+    JumpIfZero(usize),
+    /// `]`: jump to the instruction after the matching `[` if the cell is non-zero.
+    JumpIfNotZero(usize),
+    /// `[-]` or `[+]`: clear the current cell.
+    SetZero,
+    /// One target of a `[->+<]`-style loop: if the current cell is non-zero,
+    /// add it times `factor` to the cell at `offset`.
+    MulAdd {
+        offset: isize,
+        factor: i64,
+    },
+    /// `[>]` or `[<]`: move by `step` until the current cell is zero.
+    Scan(isize),
+    /// `#`, when debug dumps are enabled: stop so the tape can be inspected.
+    Dump,
+}
+
+/// An [`Op`] together with the character index it was compiled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    pub position: usize,
+}
+
+/// Brainfuck source parsed once into a flat instruction vector.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    source_len: usize,
+}
+
+impl Program {
+    /// Parses `source` into one instruction per command, without any optimization.
+    pub fn compile(source: &str) -> Result<Self, MachineError> {
+        Self::compile_with(source, &Optimizations::none())
+    }
+
+    /// Parses `source`, dropping every non-command character and pairing up the loops,
+    /// then runs the enabled optimization passes.
+    pub fn compile_with(source: &str, optimizations: &Optimizations) -> Result<Self, MachineError> {
+        Self::parse(source, optimizations, false)
+    }
+
+    /// Like [`compile_with`](Program::compile_with), but `#` becomes an [`Op::Dump`]
+    /// instead of being a comment.
+    pub fn compile_with_dumps(
+        source: &str,
+        optimizations: &Optimizations,
+    ) -> Result<Self, MachineError> {
+        Self::parse(source, optimizations, true)
+    }
+
+    fn parse(
+        source: &str,
+        optimizations: &Optimizations,
+        dumps: bool,
+    ) -> Result<Self, MachineError> {
+        let mut instructions: Vec<Instruction> = Vec::new();
+        let mut open_loops = Vec::new();
+        let mut source_len = 0;
+
+        for (position, c) in source.chars().enumerate() {
+            source_len = position + 1;
+            let op = match c {
+                '>' => Op::Move(1),
+                '<' => Op::Move(-1),
+                '+' => Op::Add(1),
+                '-' => Op::Add(-1),
+                '.' => Op::Output,
+                ',' => Op::Input,
+                '[' => {
+                    open_loops.push(instructions.len());
+                    Op::JumpIfZero(0) // Patched when the matching `]` is found
+                }
+                ']' => {
+                    let start = open_loops.pop().ok_or(MachineError::UnbalancedBrackets)?;
+                    let end = instructions.len();
+                    instructions[start].op = Op::JumpIfZero(end + 1);
+                    Op::JumpIfNotZero(start + 1)
+                }
+                '#' if dumps => Op::Dump,
+                _ => continue,
+            };
+            instructions.push(Instruction { op, position });
+        }
+
+        if !open_loops.is_empty() {
+            return Err(MachineError::UnbalancedBrackets);
+        }
+
+        Ok(Self {
+            instructions: optimizer::optimize(instructions, optimizations),
+            source_len,
+        })
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Number of characters in the source this program was compiled from.
+    pub fn source_len(&self) -> usize {
+        self.source_len
+    }
+}
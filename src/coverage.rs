@@ -0,0 +1,247 @@
+use crate::optimizer::Optimizations;
+use crate::profiler::{LoopProfile, Profile};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Which commands and loop bodies of a program ran, added up over any number of runs.
+///
+/// Runs are recorded with [`Machine::with_profiling`](crate::Machine::with_profiling).
+/// Compile them with [`Coverage::optimizations`] so every loop stays a loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    source: String,
+    /// Times each character ran, zero for comments.
+    hits: Vec<u64>,
+    /// Every loop of the source, in order of the `[`.
+    loops: Vec<LoopProfile>,
+    runs: u64,
+}
+
+impl Coverage {
+    /// Optimizations that keep the counts exact: runs are folded, loops are left alone.
+    pub fn optimizations() -> Optimizations {
+        Optimizations {
+            fold_runs: true,
+            ..Optimizations::none()
+        }
+    }
+
+    /// Empty coverage for `source`.
+    pub fn new(source: &str) -> Self {
+        let mut loops = Vec::new();
+        let mut open_loops = Vec::new();
+        for (position, c) in source.chars().enumerate() {
+            match c {
+                '[' => {
+                    open_loops.push(loops.len());
+                    loops.push(LoopProfile {
+                        start: position,
+                        end: position,
+                        entries: 0,
+                        iterations: 0,
+                    });
+                }
+                ']' => {
+                    if let Some(i) = open_loops.pop() {
+                        loops[i].end = position;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self {
+            source: source.to_string(),
+            hits: vec![0; source.chars().count()],
+            loops,
+            runs: 0,
+        }
+    }
+
+    /// The source this coverage is for.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Adds one run of the program.
+    pub fn add(&mut self, profile: &Profile) {
+        let hits = profile.character_hits(self.hits.len());
+        for ((total, c), count) in self.hits.iter_mut().zip(self.source.chars()).zip(hits) {
+            if is_command(c) {
+                *total += count;
+            }
+        }
+        for profiled in profile.loops() {
+            if let Some(l) = self.loops.iter_mut().find(|l| l.start == profiled.start) {
+                l.entries += profiled.entries;
+                l.iterations += profiled.iterations;
+            }
+        }
+        self.runs += 1;
+    }
+
+    /// Number of runs added so far.
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    /// Times each character ran over all runs. Comments are always zero.
+    pub fn character_hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// Whether the character at `index` ever ran, or `None` if it is not a command.
+    pub fn is_covered(&self, index: usize) -> Option<bool> {
+        let c = self.source.chars().nth(index)?;
+        is_command(c).then(|| self.hits[index] > 0)
+    }
+
+    /// Counts of every loop, summed over all runs. A loop body ran if `iterations > 0`.
+    pub fn loops(&self) -> &[LoopProfile] {
+        &self.loops
+    }
+
+    /// The coverage as an lcov record for the file at `path`.
+    ///
+    /// A line ran if any command on it ran. Every loop is a branch on the line of
+    /// its `[`, taken as often as its body ran.
+    pub fn to_lcov(&self, path: &str) -> String {
+        let mut record = LcovRecord::default();
+        let mut line = 1;
+        for (c, &hits) in self.source.chars().zip(&self.hits) {
+            if is_command(c) {
+                let count = record.lines.entry(line).or_default();
+                *count = (*count).max(hits);
+            }
+            if c == '\n' {
+                line += 1;
+            }
+        }
+        for (i, l) in self.loops.iter().enumerate() {
+            let line = self.line_of(l.start);
+            let taken = (l.entries > 0).then_some(l.iterations);
+            record.branches.insert((line, i), taken);
+        }
+        record.to_string(path)
+    }
+
+    /// `lcov` with the record for `path` replaced by the sum of it and this coverage.
+    /// Records of other files are kept as they are.
+    pub fn merge_into_lcov(&self, lcov: &str, path: &str) -> String {
+        let mut merged = String::new();
+        let mut previous = None;
+        let mut test_name = "TN:";
+        let mut current: Option<(&str, String)> = None;
+        for line in lcov.lines() {
+            if line.starts_with("TN:") {
+                test_name = line;
+                continue;
+            }
+            if let Some(file) = line.strip_prefix("SF:") {
+                current = Some((file, format!("{test_name}\n")));
+            }
+            let Some((file, record)) = &mut current else {
+                continue;
+            };
+            record.push_str(line);
+            record.push('\n');
+            if line == "end_of_record" {
+                if *file == path {
+                    previous = Some(LcovRecord::parse(record));
+                } else {
+                    merged.push_str(record);
+                }
+                current = None;
+            }
+        }
+
+        let mut record = LcovRecord::parse(&self.to_lcov(path));
+        if let Some(previous) = previous {
+            record.add(&previous);
+        }
+        merged.push_str(&record.to_string(path));
+        merged
+    }
+
+    fn line_of(&self, position: usize) -> usize {
+        1 + self
+            .source
+            .chars()
+            .take(position)
+            .filter(|&c| c == '\n')
+            .count()
+    }
+}
+
+fn is_command(c: char) -> bool {
+    "+-<>[].,".contains(c)
+}
+
+/// The counts of one `SF:` record of an lcov file.
+#[derive(Debug, Default)]
+struct LcovRecord {
+    /// Hits by line number.
+    lines: BTreeMap<usize, u64>,
+    /// Times taken by line and branch number, `None` if the branch was never reached.
+    branches: BTreeMap<(usize, usize), Option<u64>>,
+}
+
+impl LcovRecord {
+    fn parse(record: &str) -> Self {
+        let mut parsed = Self::default();
+        for line in record.lines() {
+            if let Some(data) = line.strip_prefix("DA:") {
+                let mut fields = data.split(',');
+                if let (Some(Ok(line)), Some(Ok(hits))) =
+                    (fields.next().map(str::parse), fields.next().map(str::parse))
+                {
+                    parsed.lines.insert(line, hits);
+                }
+            } else if let Some(data) = line.strip_prefix("BRDA:") {
+                let fields: Vec<&str> = data.split(',').collect();
+                if let [line, _, branch, taken] = fields[..] {
+                    if let (Ok(line), Ok(branch)) = (line.parse(), branch.parse()) {
+                        parsed.branches.insert((line, branch), taken.parse().ok());
+                    }
+                }
+            }
+        }
+        parsed
+    }
+
+    fn add(&mut self, other: &LcovRecord) {
+        for (&line, &hits) in &other.lines {
+            *self.lines.entry(line).or_default() += hits;
+        }
+        for (&key, &taken) in &other.branches {
+            let total = self.branches.entry(key).or_default();
+            *total = match (*total, taken) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            };
+        }
+    }
+
+    fn to_string(&self, path: &str) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "TN:");
+        let _ = writeln!(text, "SF:{path}");
+        for (&(line, branch), taken) in &self.branches {
+            let taken = taken.map_or("-".to_string(), |taken| taken.to_string());
+            let _ = writeln!(text, "BRDA:{line},0,{branch},{taken}");
+        }
+        let branches_hit = self.branches.values().filter(|t| t.is_some_and(|t| t > 0));
+        let _ = writeln!(text, "BRF:{}", self.branches.len());
+        let _ = writeln!(text, "BRH:{}", branches_hit.count());
+        for (line, hits) in &self.lines {
+            let _ = writeln!(text, "DA:{line},{hits}");
+        }
+        let _ = writeln!(text, "LF:{}", self.lines.len());
+        let _ = writeln!(
+            text,
+            "LH:{}",
+            self.lines.values().filter(|&&h| h > 0).count()
+        );
+        let _ = writeln!(text, "end_of_record");
+        text
+    }
+}
@@ -0,0 +1,287 @@
+use crate::machine::{CellWrite, Machine};
+use std::fmt;
+
+/// What a [`Watchpoint`] reacts to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Any instruction that stores into the cell, even the value it already had.
+    Write,
+    /// A write that gives the cell a different value.
+    #[default]
+    Change,
+}
+
+impl WatchKind {
+    pub const ALL: [WatchKind; 2] = [WatchKind::Write, WatchKind::Change];
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchKind::Write => write!(f, "Written"),
+            WatchKind::Change => write!(f, "Changed"),
+        }
+    }
+}
+
+/// Pauses a run when a cell is written or changes value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// Logical index of the watched cell.
+    pub cell: isize,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// True if `write` is one this watchpoint is waiting for.
+    pub fn is_triggered_by(&self, write: &CellWrite) -> bool {
+        write.cell == self.cell && (self.kind == WatchKind::Write || write.old != write.new)
+    }
+}
+
+/// A syntax error in a [`Condition`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    pub message: String,
+    /// Character index in the condition where the error was found.
+    pub position: usize,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at character {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+/// An expression over the machine state, like `cell[12] == 0 && ptr > 40`.
+///
+/// Values are integers and comparisons give `1` or `0`. The names are
+/// `ptr` (the logical index of the current cell), `cell` (its value),
+/// `cell[i]` (the value of cell `i`, `0` where the tape does not reach yet)
+/// and `steps` (the number of instructions executed). Operators, loosest first:
+/// `||`, `&&`, `== != < <= > >=`, `+ -`, `* / %`, and the prefixes `!` and `-`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i128),
+    Pointer,
+    Steps,
+    Cell(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Operators of each precedence level, loosest first.
+const LEVELS: [&[(&str, BinaryOp)]; 5] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            position: 0,
+        };
+        let expr = parser.binary(0)?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("Unexpected character"));
+        }
+        Ok(Self { expr })
+    }
+
+    /// True if the expression is non-zero for the current state of `machine`.
+    pub fn is_true(&self, machine: &Machine) -> bool {
+        self.expr.eval(machine) != 0
+    }
+}
+
+impl Expr {
+    fn eval(&self, machine: &Machine) -> i128 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Pointer => machine.cell_pointer() as i128,
+            Expr::Steps => i128::from(machine.instructions_executed()),
+            Expr::Cell(index) => isize::try_from(index.eval(machine))
+                .ok()
+                .and_then(|index| machine.cell(index))
+                .map_or(0, i128::from),
+            Expr::Not(expr) => i128::from(expr.eval(machine) == 0),
+            Expr::Negate(expr) => expr.eval(machine).wrapping_neg(),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(machine);
+                // `&&` and `||` short-circuit
+                match op {
+                    BinaryOp::And if left == 0 => return 0,
+                    BinaryOp::Or if left != 0 => return 1,
+                    _ => {}
+                }
+                let right = right.eval(machine);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => i128::from(right != 0),
+                    BinaryOp::Eq => i128::from(left == right),
+                    BinaryOp::Ne => i128::from(left != right),
+                    BinaryOp::Lt => i128::from(left < right),
+                    BinaryOp::Le => i128::from(left <= right),
+                    BinaryOp::Gt => i128::from(left > right),
+                    BinaryOp::Ge => i128::from(left >= right),
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    // Dividing by zero gives zero rather than stopping the run
+                    BinaryOp::Div => left.checked_div(right).unwrap_or(0),
+                    BinaryOp::Rem => left.checked_rem(right).unwrap_or(0),
+                }
+            }
+        }
+    }
+}
+
+/// A recursive descent parser, one function per precedence level.
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> ConditionError {
+        ConditionError {
+            message: message.to_string(),
+            position: self.position,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let matches = token
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+        if matches {
+            self.position += token.chars().count();
+        }
+        matches
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ConditionError> {
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for &(token, op) in operators.iter() {
+                if self.eat(token) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ConditionError> {
+        // `!=` is handled by the comparison level, so a `!` here is always a prefix
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ConditionError> {
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            if !self.eat(")") {
+                return Err(self.error("Expected `)`"));
+            }
+            return Ok(expr);
+        }
+
+        self.skip_whitespace();
+        let start = self.position;
+        let word: String = self.chars[start..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .collect();
+        if word.is_empty() {
+            return Err(self.error("Expected a number, `ptr`, `cell` or `steps`"));
+        }
+        self.position += word.len();
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return word.parse().map(Expr::Number).map_err(|_| ConditionError {
+                message: format!("Invalid number `{word}`"),
+                position: start,
+            });
+        }
+        match word.as_str() {
+            "ptr" => Ok(Expr::Pointer),
+            "steps" => Ok(Expr::Steps),
+            "cell" if self.eat("[") => {
+                let index = self.binary(0)?;
+                if !self.eat("]") {
+                    return Err(self.error("Expected `]`"));
+                }
+                Ok(Expr::Cell(Box::new(index)))
+            }
+            "cell" => Ok(Expr::Cell(Box::new(Expr::Pointer))),
+            _ => Err(ConditionError {
+                message: format!("Unknown name `{word}`"),
+                position: start,
+            }),
+        }
+    }
+}
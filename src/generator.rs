@@ -0,0 +1,115 @@
+//! Generates Brainfuck programs that print a given text.
+
+use crate::machine::CellWidth;
+
+/// The most cells the setup loop fills with starting values.
+const MAX_CELLS: usize = 8;
+
+/// Returns a short program that prints `text`.
+///
+/// One multiplication loop first fills a few cells with values near groups of the
+/// characters in the text. Each character is then printed from whichever cell is
+/// cheapest to reach and adjust, and that cell keeps the new value for later ones.
+///
+/// With 8-bit cells the program prints the UTF-8 bytes of `text`, with wider cells
+/// it prints its characters, which the interpreter outputs as UTF-8 as well. Characters
+/// too big for a 16-bit cell print as U+FFFD. It never relies on cells wrapping around.
+pub fn text_to_brainfuck(text: &str, cell_width: CellWidth) -> String {
+    let values: Vec<u64> = if cell_width == CellWidth::Bits8 {
+        text.bytes().map(u64::from).collect()
+    } else {
+        let max_value = cell_width.max_value();
+        text.chars()
+            .map(|c| match u64::from(c) {
+                value if value > max_value => u64::from(char::REPLACEMENT_CHARACTER),
+                value => value,
+            })
+            .collect()
+    };
+    let mut distinct = values.clone();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let Some(&largest) = distinct.last() else {
+        return String::new();
+    };
+    let max_factor = ((largest as f64).sqrt() as u64 + 2).min(256);
+
+    // Without a loop everything is built from the zero in the first cell
+    let mut best = print(vec![0], &values);
+    for cells in 1..=distinct.len().min(MAX_CELLS) {
+        let centers = cluster_centers(&distinct, cells);
+        for factor in 2..=max_factor {
+            let multipliers: Vec<u64> = centers
+                .iter()
+                .map(|&center| (center + factor / 2) / factor)
+                .collect();
+            let mut program = setup(factor, &multipliers);
+            if program.len() >= best.len() {
+                continue;
+            }
+            let start = std::iter::once(0)
+                .chain(multipliers.iter().map(|&multiplier| multiplier * factor))
+                .collect();
+            program.push_str(&print(start, &values));
+            if program.len() < best.len() {
+                best = program;
+            }
+        }
+    }
+    best
+}
+
+/// A loop that runs `factor` times and adds `multipliers` to the cells after the
+/// first one, leaving the pointer on the first cell, which ends up zero.
+fn setup(factor: u64, multipliers: &[u64]) -> String {
+    let mut program = "+".repeat(factor as usize);
+    program.push('[');
+    for &multiplier in multipliers {
+        program.push('>');
+        program.push_str(&"+".repeat(multiplier as usize));
+    }
+    program.push_str(&"<".repeat(multipliers.len()));
+    program.push_str("-]");
+    program
+}
+
+/// Prints `values` starting on the first of `cells`, each time from the cell that is
+/// closest in moves plus adjustments.
+fn print(mut cells: Vec<u64>, values: &[u64]) -> String {
+    let mut program = String::new();
+    let mut pointer = 0usize;
+    for &value in values {
+        let cost =
+            |(index, &cell): &(usize, &u64)| pointer.abs_diff(*index) as u64 + cell.abs_diff(value);
+        let (index, _) = cells
+            .iter()
+            .enumerate()
+            .min_by_key(cost)
+            .expect("there is at least one cell");
+        let moves = if index > pointer { ">" } else { "<" };
+        program.push_str(&moves.repeat(pointer.abs_diff(index)));
+        let adjust = if value > cells[index] { "+" } else { "-" };
+        program.push_str(&adjust.repeat(value.abs_diff(cells[index]) as usize));
+        program.push('.');
+        cells[index] = value;
+        pointer = index;
+    }
+    program
+}
+
+/// Splits the sorted `values` into `count` groups at the largest gaps and returns the
+/// median of each group.
+fn cluster_centers(values: &[u64], count: usize) -> Vec<u64> {
+    let mut gaps: Vec<usize> = (1..values.len()).collect();
+    gaps.sort_by_key(|&i| std::cmp::Reverse(values[i] - values[i - 1]));
+    let mut splits = gaps[..count - 1].to_vec();
+    splits.sort_unstable();
+    let mut start = 0;
+    let mut centers = Vec::with_capacity(count);
+    for end in splits.into_iter().chain([values.len()]) {
+        let group = &values[start..end];
+        centers.push(group[group.len() / 2]);
+        start = end;
+    }
+    centers
+}
@@ -1,200 +1,627 @@
+use crate::bytecode::{Op, Program};
+use crate::coverage::Coverage;
+use crate::debugger::{Condition, Watchpoint};
+use crate::machine::{CellWidth, CellWrite, EofBehavior, Machine, MachineError, Rewound, Status};
+use crate::optimizer::Optimizations;
+use crate::profiler::Profile;
 use crate::BrainfuckInterpreterInterface;
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+/// Instructions executed between two GUI updates when there is no delay.
+const BATCH_SIZE: usize = 100_000;
+
+/// Instructions the GUI can step back over. Each costs a few dozen bytes.
+const HISTORY_LIMIT: usize = 1 << 20;
+
+/// How often to look at the input box while the program waits for input.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The text of output bytes: one character per byte with 8-bit cells, UTF-8 otherwise.
+fn decode_output(bytes: &[u8], cell_width: CellWidth) -> String {
+    if cell_width == CellWidth::Bits8 {
+        bytes.iter().map(|&byte| byte as char).collect()
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// Handles to everything the GUI shows about a run, so the interpreter thread
+/// can update them without borrowing the app.
+#[derive(Clone)]
+struct MachineView {
+    data: Arc<Mutex<Vec<u64>>>,
+    tape_origin: Arc<Mutex<usize>>,
+    box_index: Arc<Mutex<usize>>,
+    letter_index: Arc<Mutex<usize>>,
+    output: Arc<Mutex<String>>,
+    output_bytes: Arc<Mutex<Vec<u8>>>,
+    input_text: Arc<Mutex<String>>,
+    warn: Arc<Mutex<bool>>,
+    warn_message: Arc<Mutex<String>>,
+    profile: Arc<Mutex<Option<Profile>>>,
+}
+
+impl MachineView {
+    fn new(app: &BrainfuckInterpreterInterface) -> Self {
+        Self {
+            data: Arc::clone(&app.data),
+            tape_origin: Arc::clone(&app.tape_origin),
+            box_index: Arc::clone(&app.box_index),
+            letter_index: Arc::clone(&app.letter_index),
+            output: Arc::clone(&app.output),
+            output_bytes: Arc::clone(&app.output_bytes),
+            input_text: Arc::clone(&app.input_text),
+            warn: Arc::clone(&app.warn),
+            warn_message: Arc::clone(&app.warn_message),
+            profile: Arc::clone(&app.profile),
+        }
+    }
+
+    /// Gives the first character of the input box to a machine waiting for input.
+    /// Returns true if the machine has to keep waiting for the user to type something.
+    fn feed_input(&self, machine: &mut Machine) -> bool {
+        let mut locked_text = self.input_text.lock().unwrap();
+        match locked_text.chars().next() {
+            Some(c) => {
+                machine.push_input_char(c);
+                locked_text.drain(..c.len_utf8());
+                false
+            }
+            // An empty input box is the end of input, unless the program should wait
+            None if machine.eof_behavior() != EofBehavior::Block => {
+                machine.close_input();
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Copies the new output, the tape and the pointers of `machine` into the GUI state.
+    fn update(&self, machine: &mut Machine) {
+        let output = machine.take_output();
+        *self.output.lock().unwrap() += &decode_output(&output, machine.cell_width());
+        self.output_bytes.lock().unwrap().extend_from_slice(&output);
+        {
+            let mut data = self.data.lock().unwrap();
+            if data.len() != machine.tape().len() {
+                data.resize(machine.tape().len(), 0);
+            }
+            data.copy_from_slice(machine.tape());
+        }
+        *self.box_index.lock().unwrap() = machine.data_pointer();
+        *self.tape_origin.lock().unwrap() = machine.origin();
+        *self.letter_index.lock().unwrap() = machine.source_position();
+        if let Some(profile) = machine.profile() {
+            *self.profile.lock().unwrap() = Some(profile.clone());
+        }
+    }
+
+    /// Undoes what the GUI did with the output and input of a rewound instruction.
+    /// The output text is only decoded again by [`redecode_output`](MachineView::redecode_output).
+    fn rewind(&self, machine: &mut Machine, rewound: Rewound) {
+        let mut output_bytes = self.output_bytes.lock().unwrap();
+        let kept = output_bytes.len().saturating_sub(rewound.taken_output);
+        output_bytes.truncate(kept);
+        // The value the instruction read goes back from the machine into the input box
+        if let Some(value) = rewound.input {
+            machine.pop_input();
+            let c = u32::try_from(value)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.input_text.lock().unwrap().insert(0, c);
+        }
+        // An empty input box closes the input again when the machine next needs some
+        machine.reopen_input();
+    }
+
+    /// Decodes the output text again from the remaining output bytes.
+    fn redecode_output(&self, cell_width: CellWidth) {
+        *self.output.lock().unwrap() =
+            decode_output(&self.output_bytes.lock().unwrap(), cell_width);
+    }
+
+    fn warn(&self, e: MachineError) {
+        *self.warn.lock().unwrap() = true;
+        *self.warn_message.lock().unwrap() = e.to_string();
+    }
+}
+
+/// Everything besides the Pause button that can stop a run.
+struct PauseRules {
+    /// Instruction to pause at for Step Over and Run to Cursor.
+    pause_at: Option<usize>,
+    /// Which instructions have a breakpoint, one more than the program for the end.
+    stops: Vec<bool>,
+    /// The break condition and its text.
+    condition: Option<(Condition, String)>,
+    condition_was_true: bool,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl PauseRules {
+    /// Looks at the machine after an instruction ran and says why it should pause, if it should.
+    fn check(&mut self, machine: &Machine) -> Option<PauseReason> {
+        let position = machine.last_position().unwrap_or_default();
+        if let Some(write) = machine.last_write() {
+            if self
+                .watchpoints
+                .iter()
+                .any(|watch| watch.is_triggered_by(&write))
+            {
+                return Some(PauseReason::Watchpoint { write, position });
+            }
+        }
+
+        if let Some((condition, text)) = &self.condition {
+            let was_true =
+                std::mem::replace(&mut self.condition_was_true, condition.is_true(machine));
+            if self.condition_was_true && !was_true {
+                return Some(PauseReason::Condition {
+                    text: text.clone(),
+                    position,
+                });
+            }
+        }
+
+        let ip = machine.instruction_pointer();
+        if self.stops[ip] {
+            Some(PauseReason::Breakpoint {
+                position: machine.source_position(),
+            })
+        } else if Some(ip) == self.pause_at {
+            Some(PauseReason::Target)
+        } else {
+            None
+        }
+    }
+
+    /// Whether nothing can pause the run, so it does not need checking after every instruction.
+    fn is_empty(&self) -> bool {
+        self.pause_at.is_none()
+            && !self.stops.contains(&true)
+            && self.condition.is_none()
+            && self.watchpoints.is_empty()
+    }
+}
+
+/// Why a run paused, shown next to the controls. Positions are character indices
+/// of the instruction that caused it.
+enum PauseReason {
+    /// Step Over or Run to Cursor arrived.
+    Target,
+    Breakpoint {
+        position: usize,
+    },
+    Dump {
+        position: usize,
+    },
+    Condition {
+        text: String,
+        position: usize,
+    },
+    Watchpoint {
+        write: CellWrite,
+        position: usize,
+    },
+}
+
+impl fmt::Display for PauseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseReason::Target => Ok(()),
+            PauseReason::Breakpoint { position } => {
+                write!(f, "Breakpoint at character {position}")
+            }
+            PauseReason::Dump { position } => write!(f, "`#` at character {position}"),
+            PauseReason::Condition { text, position } => {
+                write!(f, "`{text}` became true at character {position}")
+            }
+            PauseReason::Watchpoint { write, position } if write.old == write.new => write!(
+                f,
+                "Cell {} was written with {} at character {position}",
+                write.cell, write.new
+            ),
+            PauseReason::Watchpoint { write, position } => write!(
+                f,
+                "Cell {} changed from {} to {} at character {position}",
+                write.cell, write.old, write.new
+            ),
+        }
+    }
+}
+
 impl BrainfuckInterpreterInterface {
-    fn are_brackets_balanced(s: &str) -> bool {
-        let mut balance = 0;
-
-        for c in s.chars() {
-            match c {
-                '[' => balance += 1,
-                ']' => {
-                    if balance == 0 {
-                        return false;
-                    }
-                    balance -= 1;
-                }
-                _ => {}
+    /// Compiles the program and clears the tape, output and pointers for a new run.
+    fn load_machine(&mut self) -> bool {
+        if self.input_brainfuck.lock().unwrap().is_empty() {
+            return false;
+        }
+
+        let optimizations = if !self.optimize {
+            Optimizations::none()
+        } else if self.coverage_enabled {
+            Coverage::optimizations()
+        } else {
+            Optimizations::all()
+        };
+        let optimizations = optimizations
+            .for_overflow_policy(self.overflow_policy)
+            .for_tape_mode(self.tape_mode);
+        let source = self.input_brainfuck.lock().unwrap().clone();
+        let program = if self.debug_dumps {
+            Program::compile_with_dumps(&source, &optimizations)
+        } else {
+            Program::compile_with(&source, &optimizations)
+        };
+        let mut machine = match program {
+            Ok(program) => Machine::from_program(program)
+                .with_tape_size(self.memory_size)
+                .with_tape_mode(self.tape_mode)
+                .with_cell_width(self.cell_width)
+                .with_overflow_policy(self.overflow_policy)
+                .with_eof_behavior(self.eof_behavior),
+            Err(e) => {
+                *self.warn.lock().unwrap() = true;
+                *self.warn_message.lock().unwrap() = e.to_string();
+                return false;
             }
+        };
+
+        // Native code cannot be stepped back, so the JIT replaces the history
+        if self.jit {
+            machine = machine.with_jit();
+        } else {
+            machine = machine.with_history(HISTORY_LIMIT);
+        }
+        if self.profiling || self.coverage_enabled {
+            machine = machine.with_profiling();
         }
-        balance == 0
+
+        // The previous run counts towards the coverage, unless the code changed since
+        let previous = self.profile.lock().unwrap().take();
+        self.coverage = match (self.coverage.take(), previous) {
+            (Some(mut coverage), Some(profile)) if self.coverage_enabled => {
+                coverage.add(&profile);
+                Some(coverage)
+            }
+            (coverage, _) if self.coverage_enabled => coverage,
+            _ => None,
+        }
+        .filter(|coverage| coverage.source() == source);
+        if self.coverage_enabled && self.coverage.is_none() {
+            self.coverage = Some(Coverage::new(&source));
+        }
+
+        *self.data.lock().unwrap() = vec![0; self.memory_size];
+        *self.tape_origin.lock().unwrap() = 0;
+        *self.box_index.lock().unwrap() = 0;
+        *self.letter_index.lock().unwrap() = machine.source_position();
+        *self.profile.lock().unwrap() = machine.profile().cloned();
+        self.output.lock().unwrap().clear();
+        self.output_bytes.lock().unwrap().clear();
+        self.pause_reason.lock().unwrap().clear();
+        *self.machine.lock().unwrap() = Some(machine);
+        true
     }
 
-    pub fn start_interpreter(&mut self) {
-        let timer_running = Arc::clone(&self.timer_running);
-        let data_arc = Arc::clone(&self.data);
-        let box_index_arc = Arc::clone(&self.box_index);
-        let input_brainfuck = Arc::clone(&self.input_brainfuck);
-        let input_text = Arc::clone(&self.input_text);
-        let output_brainfuck = Arc::clone(&self.output);
-        let delay_arc = Arc::clone(&self.delay);
-        let letter_index_arc = Arc::clone(&self.letter_index);
-        let warn_arc = Arc::clone(&self.warn);
-        let warn_message_arc = Arc::clone(&self.warn_message);
+    /// True when a run was paused and can be resumed or stepped.
+    pub fn is_paused(&self) -> bool {
+        !*self.timer_running.lock().unwrap()
+            && self
+                .machine
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|machine| !machine.is_halted())
+    }
 
+    /// Makes sure there is a paused machine to step, starting a new run if needed.
+    fn ensure_paused(&mut self) -> bool {
         if let Some(handle) = self.timer_thread_handle.take() {
             handle.join().unwrap();
         }
+        !*self.timer_running.lock().unwrap() && (self.is_paused() || self.load_machine())
+    }
 
-        if *timer_running.lock().unwrap() || input_brainfuck.lock().unwrap().is_empty() {
+    pub fn start_interpreter(&mut self) {
+        if let Some(handle) = self.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+
+        if *self.timer_running.lock().unwrap() || !self.load_machine() {
             return; // Timer is already running or input is empty
         }
 
-        let loop_check = self
-            .input_brainfuck
-            .lock()
-            .unwrap()
-            .chars()
-            .as_str()
-            .to_string();
+        // A breakpoint on the first instruction pauses before anything runs
+        let first_breakpoint = self.machine.lock().unwrap().as_ref().and_then(|machine| {
+            self.breakpoint_instructions(machine.program())[0].then(|| PauseReason::Breakpoint {
+                position: machine.source_position(),
+            })
+        });
+        if let Some(reason) = first_breakpoint {
+            *self.pause_reason.lock().unwrap() = reason.to_string();
+            *self.scroll_to_pointer.lock().unwrap() = true;
+            return;
+        }
 
-        if !Self::are_brackets_balanced(&loop_check) {
-            *self.warn.lock().unwrap() = true;
-            *self.warn_message.lock().unwrap() = "Invalid loop structure".to_string();
+        self.spawn_interpreter(None);
+    }
+
+    /// Continues a paused run.
+    pub fn resume_interpreter(&mut self) {
+        if self.is_paused() {
+            self.spawn_interpreter(None);
+        }
+    }
+
+    /// Stops the interpreter thread but keeps the tape and instruction pointer.
+    pub fn pause_interpreter(&mut self) {
+        *self.timer_running.lock().unwrap() = false;
+        if let Some(handle) = self.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+    }
+
+    /// Executes exactly one instruction.
+    pub fn step_interpreter(&mut self) {
+        if !self.ensure_paused() {
+            return;
+        }
+
+        let view = MachineView::new(self);
+        let mut guard = self.machine.lock().unwrap();
+        let Some(machine) = guard.as_mut() else {
+            return;
+        };
+        let mut result = machine.step();
+        // A `,` that just got its input still has to run
+        if result == Ok(Status::AwaitingInput) && !view.feed_input(machine) {
+            result = machine.step();
+        }
+        view.update(machine);
+        *self.scroll_to_pointer.lock().unwrap() = true;
+        self.pause_reason.lock().unwrap().clear();
+        if let Err(e) = result {
+            view.warn(e);
+            *guard = None;
+        }
+    }
+
+    /// True when the last run left a machine with instructions to undo.
+    pub fn can_step_back(&self) -> bool {
+        !*self.timer_running.lock().unwrap()
+            && self
+                .machine
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|machine| machine.history_len() > 0)
+    }
+
+    /// Undoes the last executed instruction, including its output and input.
+    pub fn step_back_interpreter(&mut self) {
+        self.rewind_interpreter(|_| true);
+        self.pause_reason.lock().unwrap().clear();
+    }
+
+    /// Undoes instructions until a breakpoint is next, or the history runs out.
+    pub fn run_back_interpreter(&mut self) {
+        let stops = match self.machine.lock().unwrap().as_ref() {
+            Some(machine) => self.breakpoint_instructions(machine.program()),
+            None => return,
+        };
+        let reason = if self.rewind_interpreter(|machine| stops[machine.instruction_pointer()]) {
+            PauseReason::Breakpoint {
+                position: *self.letter_index.lock().unwrap(),
+            }
+            .to_string()
+        } else {
+            "Reached the oldest recorded instruction".to_string()
+        };
+        *self.pause_reason.lock().unwrap() = reason;
+    }
+
+    /// Steps back at least once, until `stop` returns true. Returns false if the
+    /// history ran out first.
+    fn rewind_interpreter(&mut self, mut stop: impl FnMut(&Machine) -> bool) -> bool {
+        if !self.can_step_back() {
+            return false;
+        }
+        if let Some(handle) = self.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+
+        let view = MachineView::new(self);
+        let mut guard = self.machine.lock().unwrap();
+        let Some(machine) = guard.as_mut() else {
+            return false;
+        };
+        let mut stopped = false;
+        while let Some(rewound) = machine.step_back() {
+            view.rewind(machine, rewound);
+            if stop(machine) {
+                stopped = true;
+                break;
+            }
+        }
+        view.redecode_output(machine.cell_width());
+        view.update(machine);
+        *self.scroll_to_pointer.lock().unwrap() = true;
+        stopped
+    }
+
+    /// Runs the loop that starts at the current instruction until it exits.
+    /// Anywhere else this is the same as a single step.
+    pub fn step_over_interpreter(&mut self) {
+        if !self.ensure_paused() {
+            return;
+        }
+
+        let loop_end = self.machine.lock().unwrap().as_ref().and_then(|machine| {
+            let instructions = machine.program().instructions();
+            match instructions.get(machine.instruction_pointer())?.op {
+                Op::JumpIfZero(end) => Some(end),
+                _ => None,
+            }
+        });
+        match loop_end {
+            Some(end) => self.spawn_interpreter(Some(end)),
+            None => self.step_interpreter(),
+        }
+    }
+
+    /// Runs until the first instruction at or after the character `position` is next.
+    pub fn run_to_position(&mut self, position: usize) {
+        if !self.ensure_paused() {
             return;
         }
 
-        data_arc.lock().unwrap().fill(0);
-        output_brainfuck.lock().unwrap().clear();
+        let target = self.machine.lock().unwrap().as_ref().and_then(|machine| {
+            machine
+                .program()
+                .instructions()
+                .iter()
+                .position(|instruction| instruction.position >= position)
+        });
+        self.spawn_interpreter(target);
+    }
+
+    /// Marks the instructions the breakpoints of the current file pause at. A breakpoint
+    /// on a comment, inside a folded run or inside a simplified loop pauses at the
+    /// next instruction.
+    fn breakpoint_instructions(&self, program: &Program) -> Vec<bool> {
+        let instructions = program.instructions();
+        let mut stops = vec![false; instructions.len() + 1];
+        for position in self.current_breakpoints() {
+            let index = instructions.partition_point(|instruction| instruction.position < position);
+            stops[index] = true;
+        }
+        stops
+    }
+
+    /// Collects everything that should pause the next run: `pause_at`, the breakpoints,
+    /// the break condition and the watchpoints. Warns and returns `None` if the
+    /// condition does not parse.
+    fn pause_rules(&self, program: &Program, pause_at: Option<usize>) -> Option<PauseRules> {
+        let condition = match self.break_condition.trim() {
+            "" => None,
+            text => match Condition::parse(text) {
+                Ok(condition) => Some((condition, text.to_string())),
+                Err(e) => {
+                    *self.warn.lock().unwrap() = true;
+                    *self.warn_message.lock().unwrap() = format!("Break condition: {e}");
+                    return None;
+                }
+            },
+        };
+        Some(PauseRules {
+            pause_at,
+            stops: self.breakpoint_instructions(program),
+            condition,
+            condition_was_true: false,
+            watchpoints: self.watchpoints.clone(),
+        })
+    }
+
+    /// Runs the current machine on a new thread until it halts, fails, is paused,
+    /// hits a breakpoint, watchpoint, break condition or `#` dump, or reaches the
+    /// instruction `pause_at`.
+    fn spawn_interpreter(&mut self, pause_at: Option<usize>) {
+        let timer_running = Arc::clone(&self.timer_running);
+        let machine_arc = Arc::clone(&self.machine);
+        let delay_arc = Arc::clone(&self.delay);
+        let scroll_to_pointer = Arc::clone(&self.scroll_to_pointer);
+        let pause_reason = Arc::clone(&self.pause_reason);
+        let view = MachineView::new(self);
+        let mut rules = {
+            let guard = self.machine.lock().unwrap();
+            let Some(machine) = guard.as_ref() else {
+                return;
+            };
+            let Some(mut rules) = self.pause_rules(machine.program(), pause_at) else {
+                return;
+            };
+            // Only a condition that becomes true pauses, not one that already is
+            rules.condition_was_true = rules
+                .condition
+                .as_ref()
+                .is_some_and(|(condition, _)| condition.is_true(machine));
+            rules
+        };
 
         *timer_running.lock().unwrap() = true;
+        pause_reason.lock().unwrap().clear();
 
         // Spawn a thread for the timer
         self.timer_thread_handle = Some(thread::spawn(move || {
-            let mut data_pointer: usize = 0;
-            let mut instruction_pointer: usize = 0;
-            let mut sleep_switch: bool = false;
             while *timer_running.lock().unwrap() {
-                if instruction_pointer >= input_brainfuck.lock().unwrap().len() {
-                    *timer_running.lock().unwrap() = false;
+                let mut guard = machine_arc.lock().unwrap();
+                let Some(machine) = guard.as_mut() else {
                     break;
+                };
+
+                // Typing into the input box after it ran empty undoes the end of input
+                if machine.is_input_closed() && !view.input_text.lock().unwrap().is_empty() {
+                    machine.reopen_input();
                 }
-                let instruction = input_brainfuck
-                    .lock()
-                    .unwrap()
-                    .chars()
-                    .nth(instruction_pointer)
-                    .unwrap();
-
-                match instruction {
-                    '>' => {
-                        data_pointer += 1;
-                        instruction_pointer += 1;
-                    }
-                    '<' => {
-                        if data_pointer != 0 {
-                            data_pointer -= 1;
-                        }
-                        else { 
-                            *warn_arc.lock().unwrap() = true;
-                            *warn_message_arc.lock().unwrap() = "Tried to go out of bounds".to_string();
-                            return;
-                        }
-                        instruction_pointer += 1;
-                    }
-                    '+' => {
-                        if data_arc.lock().unwrap()[data_pointer] == 255 {
-                            data_arc.lock().unwrap()[data_pointer] = 0;
-                        } else {
-                            data_arc.lock().unwrap()[data_pointer] += 1;
-                        }
-                        instruction_pointer += 1;
-                    }
-                    '-' => {
-                        if data_arc.lock().unwrap()[data_pointer] == 0 {
-                            data_arc.lock().unwrap()[data_pointer] = 255;
-                        } else {
-                            data_arc.lock().unwrap()[data_pointer] -= 1;
-                        }
-                        instruction_pointer += 1;
-                    }
-                    '.' => {
-                        *output_brainfuck.lock().unwrap() +=
-                            &*String::from(data_arc.lock().unwrap()[data_pointer] as char);
-                        instruction_pointer += 1;
-                    }
-                    ',' => {
-                        if !input_text.lock().unwrap().is_empty() {
-                            data_arc.lock().unwrap()[data_pointer] =
-                                input_text.lock().unwrap().chars().nth(0).unwrap() as u8;
-                            let mut locked_text = input_text.lock().unwrap();
-                            if !locked_text.is_empty() {
-                                locked_text.drain(..1);
-                            }
-                            instruction_pointer += 1;
-                        }
-                    }
-                    '[' => {
-                        if data_arc.lock().unwrap()[data_pointer] == 0 {
-                            let mut bracket_nesting = 1;
-                            let code_length = input_brainfuck.lock().unwrap().len();
-                            let mut pos = instruction_pointer + 1;
-                            while pos < code_length && bracket_nesting > 0 {
-                                let instruction =
-                                    input_brainfuck.lock().unwrap().chars().nth(pos).unwrap();
-                                if instruction == '[' {
-                                    bracket_nesting += 1;
-                                } else if instruction == ']' {
-                                    bracket_nesting -= 1;
-                                }
-                                pos += 1;
-                            }
-                            instruction_pointer = pos;
-                        } else {
-                            instruction_pointer += 1;
-                        }
-                    }
-                    ']' => {
-                        if data_arc.lock().unwrap()[data_pointer] != 0 {
-                            let mut bracket_nesting = 1;
-                            if instruction_pointer == 0 {
-                                break;
-                            }
-                            let mut pos = instruction_pointer - 1;
-                            while pos > 0 && bracket_nesting > 0 {
-                                let instruction =
-                                    input_brainfuck.lock().unwrap().chars().nth(pos).unwrap();
-                                if instruction == ']' {
-                                    bracket_nesting += 1;
-                                } else if instruction == '[' {
-                                    bracket_nesting -= 1;
-                                }
-                                if pos == 0 {
-                                    break;
-                                }
-                                pos -= 1;
-                            }
-                            instruction_pointer = pos + 1;
-                        } else {
-                            instruction_pointer += 1;
-                        }
-                    }
-                    _ => {
-                        instruction_pointer += 1;
-                        sleep_switch = true;
+
+                let delay = *delay_arc.lock().unwrap();
+                let batch = if delay == 0 { BATCH_SIZE } else { 1 };
+                let mut reason = None;
+                let result = if rules.is_empty() {
+                    machine.run_for(batch)
+                } else {
+                    machine.run_until(batch, |machine| {
+                        reason = rules.check(machine);
+                        reason.is_some()
+                    })
+                };
+                let status = match result {
+                    Ok(status) => status,
+                    Err(e) => {
+                        view.update(machine);
+                        view.warn(e);
+                        *guard = None;
+                        break;
                     }
-                }
+                };
 
-                if data_pointer >= data_arc.lock().unwrap().len() {
-                    data_arc.lock().unwrap().resize(data_pointer + 1, 0);
-                }
-                *box_index_arc.lock().unwrap() = data_pointer;
-                *letter_index_arc.lock().unwrap() = instruction_pointer;
+                let waiting = status == Status::AwaitingInput && view.feed_input(machine);
+                view.update(machine);
 
-                if !sleep_switch {
-                    thread::sleep(Duration::from_millis(*delay_arc.lock().unwrap()));
-                    sleep_switch = false;
+                if status == Status::Halted {
+                    break;
+                }
+                if status == Status::Dump {
+                    reason = Some(PauseReason::Dump {
+                        position: machine.last_position().unwrap_or_default(),
+                    });
                 }
+                if let Some(reason) = reason {
+                    *pause_reason.lock().unwrap() = reason.to_string();
+                    *scroll_to_pointer.lock().unwrap() = true;
+                    break;
+                }
+                drop(guard);
+
+                let delay = Duration::from_millis(delay);
+                thread::sleep(if waiting {
+                    delay.max(INPUT_POLL_INTERVAL)
+                } else {
+                    delay
+                });
             }
-            return;
+            *timer_running.lock().unwrap() = false;
         }));
     }
+
+    /// Stops the run and throws away its state.
     pub fn stop_interpreter(&mut self) {
-        *self.timer_running.lock().unwrap() = false;
-        if let Some(handle) = self.timer_thread_handle.take() {
-            handle.join().unwrap();
-        }
+        self.pause_interpreter();
+        *self.machine.lock().unwrap() = None;
     }
 }
@@ -0,0 +1,249 @@
+//! Compiles a [`Program`] to x86-64 machine code, see [`Machine::with_jit`](crate::Machine::with_jit).
+//!
+//! The compiled code works directly on the machine's tape and only handles what
+//! is fast and simple: arithmetic, moves inside the tape, loops, clears, multiply
+//! loops and scans. Anything else stops the code at that instruction and the
+//! interpreter executes it: `,`, `.`, `#` and moves past either end of the tape.
+//! It also stops once it used up its instruction budget, so limits and the
+//! GUI's batches work exactly like they do with the interpreter.
+
+use crate::bytecode::{Op, Program};
+use crate::machine::CellWidth;
+use crate::x86::{Assembler, JNZ, JZ, R10, R8, R9, RAX, RCX, RDI, RDX};
+use std::fmt;
+
+/// What the compiled code reads on entry and writes back before returning.
+#[repr(C)]
+struct State {
+    tape: *mut u64,
+    len: usize,
+    data_pointer: usize,
+    instruction_pointer: usize,
+    /// Instructions left to run.
+    fuel: u64,
+    /// Address of the code of every instruction, and of the end.
+    entries: *const usize,
+}
+
+/// A program compiled to machine code.
+pub(crate) struct Compiled {
+    code: ExecutableMemory,
+    entries: Vec<usize>,
+}
+
+impl fmt::Debug for Compiled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compiled({} bytes)", self.code.len)
+    }
+}
+
+impl Compiled {
+    /// Compiles `program` for cells of `cell_width`, or returns `None` if the
+    /// executable memory could not be set up.
+    pub(crate) fn new(program: &Program, cell_width: CellWidth) -> Option<Self> {
+        let (bytes, offsets) = assemble(program, cell_width)?;
+        let code = ExecutableMemory::new(&bytes)?;
+        let entries = offsets
+            .iter()
+            .map(|&offset| code.address + offset)
+            .collect();
+        Some(Self { code, entries })
+    }
+
+    /// Runs at most `fuel` instructions from `instruction_pointer`, updating the
+    /// tape and pointers in place. Returns the number of instructions executed.
+    ///
+    /// Stops at the end of the program, when the fuel runs out, or before an
+    /// instruction it leaves to the interpreter.
+    pub(crate) fn run(
+        &self,
+        tape: &mut [u64],
+        data_pointer: &mut usize,
+        instruction_pointer: &mut usize,
+        fuel: u64,
+    ) -> u64 {
+        let mut state = State {
+            tape: tape.as_mut_ptr(),
+            len: tape.len(),
+            data_pointer: *data_pointer,
+            instruction_pointer: *instruction_pointer,
+            fuel,
+            entries: self.entries.as_ptr(),
+        };
+        // SAFETY: the code was generated by `assemble` for this program. It only
+        // touches cells below `state.len` and returns through the common exit.
+        unsafe {
+            let function: extern "sysv64" fn(*mut State) = std::mem::transmute(self.code.address);
+            function(&mut state);
+        }
+        *data_pointer = state.data_pointer;
+        *instruction_pointer = state.instruction_pointer;
+        fuel - state.fuel
+    }
+}
+
+/// Pages holding the compiled code, readable and executable but not writable.
+struct ExecutableMemory {
+    address: usize,
+    len: usize,
+}
+
+// The code is never written again after it was copied in
+unsafe impl Send for ExecutableMemory {}
+unsafe impl Sync for ExecutableMemory {}
+
+impl ExecutableMemory {
+    fn new(bytes: &[u8]) -> Option<Self> {
+        let len = bytes.len().max(1);
+        // SAFETY: a fresh anonymous mapping, only written before it becomes executable
+        unsafe {
+            let address = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if address == libc::MAP_FAILED {
+                return None;
+            }
+            let memory = Self {
+                address: address as usize,
+                len,
+            };
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), address.cast::<u8>(), bytes.len());
+            if libc::mprotect(address, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None;
+            }
+            Some(memory)
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `new` and nothing runs from it anymore
+        unsafe {
+            libc::munmap(self.address as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Holds the `State` pointer the whole time.
+const STATE: u8 = RDI;
+/// Instructions left to run.
+const FUEL: u8 = 11; // r11
+
+const MOV_LOAD: u8 = 0x8B;
+const MOV_STORE: u8 = 0x89;
+
+/// `dec r11`
+fn dec_fuel(asm: &mut Assembler) {
+    asm.emit(&[0x49, 0xFF, 0xC8 | (FUEL & 7)]);
+}
+
+/// Generates the code and the offset of every instruction's entry point, plus one for the end.
+fn assemble(program: &Program, cell_width: CellWidth) -> Option<(Vec<u8>, Vec<usize>)> {
+    let instructions = program.instructions();
+    // Exits load the instruction index as a 32-bit immediate
+    if instructions.len() >= u32::MAX as usize {
+        return None;
+    }
+    let mut asm = Assembler::default();
+    let entries: Vec<usize> = (0..=instructions.len()).map(|_| asm.new_label()).collect();
+    let exits: Vec<usize> = (0..=instructions.len()).map(|_| asm.new_label()).collect();
+    let common_exit = asm.new_label();
+
+    asm.memory_op(MOV_LOAD, R8, STATE, 0);
+    asm.memory_op(MOV_LOAD, R9, STATE, 8);
+    asm.memory_op(MOV_LOAD, R10, STATE, 16);
+    asm.memory_op(MOV_LOAD, RAX, STATE, 24);
+    asm.memory_op(MOV_LOAD, FUEL, STATE, 32);
+    asm.memory_op(MOV_LOAD, RCX, STATE, 40);
+    asm.emit(&[0xFF, 0x24, 0xC1]); // jmp [rcx + rax * 8]
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let exit = exits[index];
+        asm.bind(entries[index]);
+        asm.test(FUEL);
+        asm.jump_if(JZ, exit);
+        match instruction.op {
+            Op::Add(n) => {
+                asm.load_cell(RAX, R10);
+                asm.mov_imm(RCX, n as u64);
+                asm.add(RAX, RCX);
+                asm.mask(cell_width);
+                asm.store_cell(R10, RAX);
+            }
+            Op::Move(n) => {
+                asm.offset_pointer(RAX, n, exit);
+                asm.mov(R10, RAX);
+            }
+            Op::Output | Op::Input | Op::Dump => {
+                asm.jump(exit);
+                continue;
+            }
+            Op::JumpIfZero(target) | Op::JumpIfNotZero(target) => {
+                dec_fuel(&mut asm);
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                let condition = if matches!(instruction.op, Op::JumpIfZero(_)) {
+                    JZ
+                } else {
+                    JNZ
+                };
+                asm.jump_if(condition, entries[target]);
+                continue;
+            }
+            Op::SetZero => {
+                asm.mov_imm(RAX, 0);
+                asm.store_cell(R10, RAX);
+            }
+            Op::MulAdd { offset, factor } => {
+                let done = asm.new_label();
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                asm.jump_if(JZ, done);
+                asm.offset_pointer(RCX, offset, exit);
+                asm.mov_imm(RDX, factor as u64);
+                asm.imul(RAX, RDX);
+                asm.cell_op(0x03, RAX, RCX); // add rax, [r8 + rcx * 8]
+                asm.mask(cell_width);
+                asm.store_cell(RCX, RAX);
+                asm.bind(done);
+            }
+            Op::Scan(step) => {
+                // Leaving half way is fine, the interpreter scans on from there
+                let scan = asm.new_label();
+                let done = asm.new_label();
+                asm.bind(scan);
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                asm.jump_if(JZ, done);
+                asm.offset_pointer(RAX, step, exit);
+                asm.mov(R10, RAX);
+                asm.jump(scan);
+                asm.bind(done);
+            }
+        }
+        dec_fuel(&mut asm);
+    }
+    asm.bind(entries[instructions.len()]);
+    asm.jump(exits[instructions.len()]);
+
+    for (index, &exit) in exits.iter().enumerate() {
+        asm.bind(exit);
+        asm.emit(&[0xB8]); // mov eax, index
+        asm.emit(&(index as u32).to_le_bytes());
+        asm.jump(common_exit);
+    }
+    asm.bind(common_exit);
+    asm.memory_op(MOV_STORE, R10, STATE, 16);
+    asm.memory_op(MOV_STORE, RAX, STATE, 24);
+    asm.memory_op(MOV_STORE, FUEL, STATE, 32);
+    asm.ret();
+
+    let offsets = entries.iter().map(|&label| asm.offset(label)).collect();
+    Some((asm.finish(), offsets))
+}
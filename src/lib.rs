@@ -1,7 +1,29 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod bytecode;
+mod coverage;
+mod debugger;
+mod generator;
 mod tests;
 mod interpreter;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+mod jit;
+mod machine;
+mod optimizer;
+mod profiler;
+mod transpile;
+mod x86;
 
-pub use app::BrainfuckInterpreterInterface;
+pub use app::{BrainfuckInterpreterApp, BrainfuckInterpreterInterface};
+pub use bytecode::{Instruction, Op, Program};
+pub use coverage::Coverage;
+pub use debugger::{Condition, ConditionError, WatchKind, Watchpoint};
+pub use generator::text_to_brainfuck;
+pub use machine::{
+    CellWidth, CellWrite, EofBehavior, Machine, MachineError, OverflowPolicy, Rewound, Status,
+    TapeMode,
+};
+pub use optimizer::Optimizations;
+pub use profiler::{LoopProfile, Profile};
+pub use transpile::{to_c, to_elf, to_rust, to_wasm, to_wat, TranspileOptions};
@@ -0,0 +1,829 @@
+use crate::bytecode::{Op, Program};
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+use crate::jit::Compiled;
+use crate::profiler::Profile;
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+use std::sync::Arc;
+
+/// Errors that stop a Brainfuck program from starting or continuing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineError {
+    /// The `[` and `]` in the source do not pair up.
+    UnbalancedBrackets,
+    /// The data pointer tried to move left of the first cell.
+    OutOfBounds { position: usize },
+    /// A cell went past its bounds under [`OverflowPolicy::Trap`].
+    /// `cell` is the logical index, negative cells only exist on a bidirectional tape.
+    CellOverflow { cell: isize, position: usize },
+    /// `,` ran out of input under [`EofBehavior::Error`], or under
+    /// [`EofBehavior::Block`] after the input was closed.
+    EndOfInput { position: usize },
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::UnbalancedBrackets => write!(f, "Invalid loop structure"),
+            MachineError::OutOfBounds { .. } => write!(f, "Tried to go out of bounds"),
+            MachineError::CellOverflow { cell, position } => {
+                write!(f, "Cell {cell} overflowed at instruction {position}")
+            }
+            MachineError::EndOfInput { .. } => write!(f, "Tried to read past the end of input"),
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+impl MachineError {
+    /// Character index in the source of the instruction that failed, if any.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            MachineError::UnbalancedBrackets => None,
+            MachineError::OutOfBounds { position }
+            | MachineError::CellOverflow { position, .. }
+            | MachineError::EndOfInput { position } => Some(*position),
+        }
+    }
+}
+
+/// Number of bits in a tape cell. Arithmetic wraps modulo `2^bits`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CellWidth {
+    #[default]
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl CellWidth {
+    pub const ALL: [CellWidth; 4] = [
+        CellWidth::Bits8,
+        CellWidth::Bits16,
+        CellWidth::Bits32,
+        CellWidth::Bits64,
+    ];
+
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+            CellWidth::Bits64 => 64,
+        }
+    }
+
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|width| width.bits() == bits)
+    }
+
+    /// The largest value a cell can hold.
+    pub fn max_value(self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+}
+
+impl fmt::Display for CellWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-bit", self.bits())
+    }
+}
+
+/// Shape of the tape the data pointer moves on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TapeMode {
+    /// Starts at cell 0 and grows to the right on demand. Moving left of cell 0 is an error.
+    #[default]
+    Standard,
+    /// Grows on demand in both directions, so cells can have negative indices.
+    Bidirectional,
+    /// Keeps its initial size, moving past either end wraps around to the other.
+    Circular,
+}
+
+impl TapeMode {
+    pub const ALL: [TapeMode; 3] = [
+        TapeMode::Standard,
+        TapeMode::Bidirectional,
+        TapeMode::Circular,
+    ];
+}
+
+impl fmt::Display for TapeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeMode::Standard => write!(f, "Standard"),
+            TapeMode::Bidirectional => write!(f, "Bidirectional"),
+            TapeMode::Circular => write!(f, "Circular"),
+        }
+    }
+}
+
+/// What happens when `+`, `-` or a multiply loop pushes a cell past its bounds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wrap around modulo the cell width.
+    #[default]
+    Wrap,
+    /// Clamp to 0 or the maximum cell value.
+    Saturate,
+    /// Stop with [`MachineError::CellOverflow`].
+    Trap,
+}
+
+impl OverflowPolicy {
+    pub const ALL: [OverflowPolicy; 3] = [
+        OverflowPolicy::Wrap,
+        OverflowPolicy::Saturate,
+        OverflowPolicy::Trap,
+    ];
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowPolicy::Wrap => write!(f, "Wrap"),
+            OverflowPolicy::Saturate => write!(f, "Saturate"),
+            OverflowPolicy::Trap => write!(f, "Trap"),
+        }
+    }
+}
+
+/// What `,` does once the input has been closed and fully consumed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofBehavior {
+    /// Leave the current cell as it is.
+    #[default]
+    Unchanged,
+    /// Set the current cell to 0.
+    Zero,
+    /// Set the current cell to -1, which wraps to the maximum cell value.
+    MinusOne,
+    /// Never treat input as finished: whoever drives the machine keeps waiting
+    /// for more. Fails with [`MachineError::EndOfInput`] if the input is closed anyway.
+    Block,
+    /// Fail with [`MachineError::EndOfInput`].
+    Error,
+}
+
+impl EofBehavior {
+    pub const ALL: [EofBehavior; 5] = [
+        EofBehavior::Unchanged,
+        EofBehavior::Zero,
+        EofBehavior::MinusOne,
+        EofBehavior::Block,
+        EofBehavior::Error,
+    ];
+}
+
+impl fmt::Display for EofBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EofBehavior::Unchanged => write!(f, "Unchanged"),
+            EofBehavior::Zero => write!(f, "Set to 0"),
+            EofBehavior::MinusOne => write!(f, "Set to -1"),
+            EofBehavior::Block => write!(f, "Block"),
+            EofBehavior::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// What the machine is doing after a call to [`Machine::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// There are more instructions to execute.
+    Running,
+    /// The current instruction is `,` and the input queue is empty.
+    AwaitingInput,
+    /// The instruction pointer ran past the end of the program.
+    Halted,
+    /// A `#` debug dump was just executed.
+    Dump,
+}
+
+/// A cell written by the last instruction, see [`Machine::last_write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellWrite {
+    /// Logical index of the cell, negative cells only exist on a bidirectional tape.
+    pub cell: isize,
+    pub old: u64,
+    pub new: u64,
+}
+
+/// What [`Machine::step_back`] could not undo by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rewound {
+    /// Bytes the instruction wrote that were already removed with
+    /// [`take_output`](Machine::take_output). The caller has to drop them from its copy.
+    pub taken_output: usize,
+    /// The value the instruction read. It is back at the front of the input queue.
+    pub input: Option<u64>,
+}
+
+/// How to undo one executed instruction.
+#[derive(Debug, Clone)]
+struct Undo {
+    instruction_pointer: usize,
+    data_pointer: usize,
+    origin: usize,
+    tape_len: usize,
+    /// Physical index and old value of the cell the instruction wrote.
+    write: Option<(usize, u64)>,
+    input: Option<u64>,
+    /// Bytes of output the instruction wrote.
+    output_bytes: usize,
+    /// Highest cell the profile had seen before the instruction, if profiling.
+    profile_max_cell: Option<isize>,
+}
+
+/// A synchronous Brainfuck machine: program, tape, pointers and I/O queues.
+///
+/// The machine does not know anything about the GUI, it is driven by calling
+/// [`step`](Machine::step), [`run`](Machine::run) or [`run_for`](Machine::run_for).
+#[derive(Debug, Clone)]
+pub struct Machine {
+    program: Program,
+    tape: Vec<u64>,
+    tape_mode: TapeMode,
+    /// Index in `tape` of logical cell 0. Only moves on a bidirectional tape.
+    origin: usize,
+    cell_width: CellWidth,
+    overflow_policy: OverflowPolicy,
+    data_pointer: usize,
+    instruction_pointer: usize,
+    input: VecDeque<u64>,
+    input_closed: bool,
+    eof_behavior: EofBehavior,
+    output: Vec<u8>,
+    executed: u64,
+    /// Source position of the last executed instruction.
+    last_position: Option<usize>,
+    last_write: Option<CellWrite>,
+    /// Undo records of the most recent instructions, oldest first.
+    history: VecDeque<Undo>,
+    history_limit: usize,
+    profile: Option<Profile>,
+    /// Whether [`run_for`](Machine::run_for) may run native code.
+    jit: bool,
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    compiled: Option<Arc<Compiled>>,
+}
+
+impl Machine {
+    pub const DEFAULT_TAPE_SIZE: usize = 256;
+
+    /// Creates a machine for `source`, failing if the loops are not balanced.
+    pub fn new(source: &str) -> Result<Self, MachineError> {
+        Ok(Self::from_program(Program::compile(source)?))
+    }
+
+    /// Creates a machine for an already compiled program.
+    pub fn from_program(program: Program) -> Self {
+        Self {
+            program,
+            tape: vec![0; Self::DEFAULT_TAPE_SIZE],
+            tape_mode: TapeMode::default(),
+            origin: 0,
+            cell_width: CellWidth::default(),
+            overflow_policy: OverflowPolicy::default(),
+            data_pointer: 0,
+            instruction_pointer: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            eof_behavior: EofBehavior::default(),
+            output: Vec::new(),
+            executed: 0,
+            last_position: None,
+            last_write: None,
+            history: VecDeque::new(),
+            history_limit: 0,
+            profile: None,
+            jit: false,
+            #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+            compiled: None,
+        }
+    }
+
+    /// Sets the initial number of cells. Unless the tape is circular, it still grows on demand.
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape = vec![0; size.max(1)];
+        self
+    }
+
+    pub fn with_tape_mode(mut self, tape_mode: TapeMode) -> Self {
+        self.tape_mode = tape_mode;
+        self
+    }
+
+    pub fn with_cell_width(mut self, cell_width: CellWidth) -> Self {
+        self.cell_width = cell_width;
+        self
+    }
+
+    pub fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    pub fn with_eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// Counts how often every instruction runs, see [`profile`](Machine::profile).
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Profile::new(&self.program));
+        self
+    }
+
+    /// Remembers how to undo the last `limit` instructions, for [`step_back`](Machine::step_back).
+    /// Off (`0`) by default, since every recorded instruction costs memory.
+    pub fn with_history(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    /// Lets [`run`](Machine::run) and [`run_for`](Machine::run_for) compile the program
+    /// to native code on first use. The results, including the instruction count, are
+    /// the same as when interpreting.
+    ///
+    /// This needs the `jit` cargo feature and x86-64. Elsewhere, and with history,
+    /// profiling or cells that do not wrap, the machine keeps interpreting.
+    pub fn with_jit(mut self) -> Self {
+        self.jit = true;
+        self
+    }
+
+    pub fn tape(&self) -> &[u64] {
+        &self.tape
+    }
+
+    pub fn cell_width(&self) -> CellWidth {
+        self.cell_width
+    }
+
+    pub fn tape_mode(&self) -> TapeMode {
+        self.tape_mode
+    }
+
+    /// Index in [`tape`](Machine::tape) of cell 0. Cells before it have negative indices.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// Index in [`tape`](Machine::tape) of the current cell.
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Index of the next instruction in [`Program::instructions`].
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    /// Index (in characters) of the next instruction in the source.
+    pub fn source_position(&self) -> usize {
+        self.program
+            .instructions()
+            .get(self.instruction_pointer)
+            .map_or(self.program.source_len(), |instruction| {
+                instruction.position
+            })
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.instruction_pointer >= self.program.len()
+    }
+
+    /// Number of instructions executed so far.
+    pub fn instructions_executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Execution counts so far, if the machine was created [`with_profiling`](Machine::with_profiling).
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Index (in characters) in the source of the instruction executed last.
+    pub fn last_position(&self) -> Option<usize> {
+        self.last_position
+    }
+
+    /// The cell the last executed instruction wrote to, if it wrote one.
+    pub fn last_write(&self) -> Option<CellWrite> {
+        self.last_write
+    }
+
+    /// Logical index of the current cell, see [`origin`](Machine::origin).
+    pub fn cell_pointer(&self) -> isize {
+        self.data_pointer as isize - self.origin as isize
+    }
+
+    /// Value of the cell at a logical index, or `None` if the tape does not reach it yet.
+    pub fn cell(&self, index: isize) -> Option<u64> {
+        let physical = index.checked_add(self.origin as isize)?;
+        self.tape.get(usize::try_from(physical).ok()?).copied()
+    }
+
+    /// Queues bytes to be read by `,`.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes.iter().map(|&byte| u64::from(byte)));
+    }
+
+    /// Queues a character to be read by a single `,`, as its code point
+    /// truncated to the cell width.
+    pub fn push_input_char(&mut self, c: char) {
+        self.input.push_back(u64::from(c));
+    }
+
+    /// Marks the end of input. Once the queue is empty, `,` follows the [`EofBehavior`]
+    /// instead of returning [`Status::AwaitingInput`].
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Undoes [`close_input`](Machine::close_input), for interactive input that
+    /// was only empty for a while.
+    pub fn reopen_input(&mut self) {
+        self.input_closed = false;
+    }
+
+    pub fn is_input_closed(&self) -> bool {
+        self.input_closed
+    }
+
+    pub fn eof_behavior(&self) -> EofBehavior {
+        self.eof_behavior
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Removes and returns the queued input that `,` has not read yet.
+    pub fn take_input(&mut self) -> Vec<u64> {
+        self.input.drain(..).collect()
+    }
+
+    /// Removes the value the next `,` would read.
+    pub fn pop_input(&mut self) -> Option<u64> {
+        self.input.pop_front()
+    }
+
+    /// Removes and returns everything written by `.` so far.
+    ///
+    /// With 8-bit cells every `.` writes one raw byte. With wider cells the value
+    /// is written as the UTF-8 encoding of that code point, or U+FFFD if it is not one.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Describes the used part of the tape, with the current cell in brackets,
+    /// e.g. `cell 1: 72 [101] 0`. This is what a `#` dump prints.
+    pub fn dump(&self) -> String {
+        let used = self
+            .tape
+            .iter()
+            .rposition(|&cell| cell != 0)
+            .map_or(0, |last| last + 1)
+            .max(self.data_pointer + 1);
+        let cells: Vec<String> = self.tape[..used]
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == self.data_pointer {
+                    format!("[{cell}]")
+                } else {
+                    cell.to_string()
+                }
+            })
+            .collect();
+        let cell = self.data_pointer as isize - self.origin as isize;
+        format!("cell {cell}: {}", cells.join(" "))
+    }
+
+    /// Executes one instruction.
+    pub fn step(&mut self) -> Result<Status, MachineError> {
+        let mask = self.cell_width.max_value();
+        let index = self.instruction_pointer;
+        let Some(&instruction) = self.program.instructions().get(index) else {
+            return Ok(Status::Halted);
+        };
+
+        let mut next = self.instruction_pointer + 1;
+        let mut undo = (self.history_limit > 0).then_some(Undo {
+            instruction_pointer: self.instruction_pointer,
+            data_pointer: self.data_pointer,
+            origin: self.origin,
+            tape_len: self.tape.len(),
+            write: None,
+            input: None,
+            output_bytes: self.output.len(),
+            profile_max_cell: self.profile.as_ref().map(Profile::max_cell),
+        });
+        self.last_write = None;
+        match instruction.op {
+            Op::Add(n) => {
+                self.add_to_cell(self.data_pointer, i128::from(n), instruction.position)?
+            }
+            Op::Move(n) => self.move_pointer(n, instruction.position)?,
+            Op::Output => self.write_output(self.tape[self.data_pointer]),
+            Op::Input => match self.input.pop_front() {
+                Some(value) => {
+                    if let Some(undo) = &mut undo {
+                        undo.input = Some(value);
+                    }
+                    self.write_cell(self.data_pointer, value & mask)
+                }
+                None if self.input_closed => match self.eof_behavior {
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::Zero => self.write_cell(self.data_pointer, 0),
+                    EofBehavior::MinusOne => self.write_cell(self.data_pointer, mask),
+                    EofBehavior::Block | EofBehavior::Error => {
+                        return Err(MachineError::EndOfInput {
+                            position: instruction.position,
+                        })
+                    }
+                },
+                None => return Ok(Status::AwaitingInput),
+            },
+            Op::JumpIfZero(target) => {
+                if self.tape[self.data_pointer] == 0 {
+                    next = target;
+                }
+            }
+            Op::JumpIfNotZero(target) => {
+                if self.tape[self.data_pointer] != 0 {
+                    next = target;
+                }
+            }
+            Op::SetZero => self.write_cell(self.data_pointer, 0),
+            Op::MulAdd { offset, factor } => {
+                let value = self.tape[self.data_pointer];
+                if value != 0 {
+                    let target = self.cell_index(offset, instruction.position)?;
+                    let amount = i128::from(value) * i128::from(factor);
+                    self.add_to_cell(target, amount, instruction.position)?;
+                }
+            }
+            Op::Scan(step) => {
+                // A circular tape may have no zero cell, so one step scans at most
+                // one lap and stays on the scan until it finds one
+                for _ in 0..self.tape.len() {
+                    if self.tape[self.data_pointer] == 0 {
+                        break;
+                    }
+                    self.move_pointer(step, instruction.position)?;
+                }
+                if self.tape[self.data_pointer] != 0 {
+                    next = index;
+                }
+            }
+            Op::Dump => {}
+        }
+        self.instruction_pointer = next;
+        self.executed += 1;
+        self.last_position = Some(instruction.position);
+        if let Some(profile) = &mut self.profile {
+            let cell = self.data_pointer as isize - self.origin as isize;
+            let written = self.last_write.map_or(cell, |write| write.cell);
+            profile.record(index, cell.max(written));
+        }
+        if let Some(mut undo) = undo {
+            undo.write = self.last_write.map(|write| {
+                let index = (write.cell + self.origin as isize) as usize;
+                (index, write.old)
+            });
+            undo.output_bytes = self.output.len() - undo.output_bytes;
+            if self.history.len() == self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(undo);
+        }
+
+        Ok(if instruction.op == Op::Dump {
+            Status::Dump
+        } else if self.is_halted() {
+            Status::Halted
+        } else {
+            Status::Running
+        })
+    }
+
+    /// Number of instructions [`step_back`](Machine::step_back) can undo.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undoes the last executed instruction: its tape write, pointer move, tape growth,
+    /// input and output. Returns `None` if there is nothing left in the history.
+    pub fn step_back(&mut self) -> Option<Rewound> {
+        let undo = self.history.pop_back()?;
+        if let Some((index, old)) = undo.write {
+            self.tape[index] = old;
+        }
+        self.tape.drain(..self.origin - undo.origin);
+        self.tape.truncate(undo.tape_len);
+        self.origin = undo.origin;
+        self.data_pointer = undo.data_pointer;
+        self.instruction_pointer = undo.instruction_pointer;
+        self.executed -= 1;
+        if let Some(value) = undo.input {
+            self.input.push_front(value);
+        }
+        let kept = undo.output_bytes.min(self.output.len());
+        self.output.truncate(self.output.len() - kept);
+        if let (Some(profile), Some(max_cell)) = (&mut self.profile, undo.profile_max_cell) {
+            profile.unrecord(undo.instruction_pointer, max_cell);
+        }
+
+        self.last_write = None;
+        self.last_position = self
+            .history
+            .back()
+            .map(|undo| self.program.instructions()[undo.instruction_pointer].position);
+        Some(Rewound {
+            taken_output: undo.output_bytes - kept,
+            input: undo.input,
+        })
+    }
+
+    /// Runs until the program halts, needs input, hits a `#` dump or fails.
+    pub fn run(&mut self) -> Result<Status, MachineError> {
+        loop {
+            let status = self.run_for(usize::MAX)?;
+            if status != Status::Running {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Runs at most `n` instructions. Returns [`Status::Running`] if the budget ran out.
+    pub fn run_for(&mut self, n: usize) -> Result<Status, MachineError> {
+        #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+        if let Some(compiled) = self.compiled() {
+            return self.run_compiled(&compiled, n);
+        }
+        self.run_until(n, |_| false)
+    }
+
+    /// The native code for the program, compiled on first use, if it can be used.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    fn compiled(&mut self) -> Option<Arc<Compiled>> {
+        // The native code does not record anything and always wraps
+        if !self.jit
+            || self.history_limit > 0
+            || self.profile.is_some()
+            || self.overflow_policy != OverflowPolicy::Wrap
+        {
+            return None;
+        }
+        if self.compiled.is_none() {
+            self.compiled = Compiled::new(&self.program, self.cell_width).map(Arc::new);
+            // Fall back to interpreting for good if there is no executable memory
+            self.jit = self.compiled.is_some();
+        }
+        self.compiled.clone()
+    }
+
+    /// [`run_for`](Machine::run_for) with native code, interpreting only the
+    /// instructions the native code stops at.
+    #[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+    fn run_compiled(&mut self, compiled: &Compiled, n: usize) -> Result<Status, MachineError> {
+        let mut remaining = n as u64;
+        loop {
+            let executed = compiled.run(
+                &mut self.tape,
+                &mut self.data_pointer,
+                &mut self.instruction_pointer,
+                remaining,
+            );
+            if executed > 0 {
+                self.executed += executed;
+                self.last_write = None;
+                remaining -= executed;
+            }
+            if self.is_halted() {
+                return Ok(Status::Halted);
+            }
+            if remaining == 0 {
+                return Ok(Status::Running);
+            }
+            let status = self.step()?;
+            if status != Status::Running {
+                return Ok(status);
+            }
+            remaining -= 1;
+        }
+    }
+
+    /// Like [`run_for`](Machine::run_for), but also stops early once `pause` returns true.
+    /// `pause` is checked after every instruction, so at least one instruction runs.
+    pub fn run_until(
+        &mut self,
+        n: usize,
+        mut pause: impl FnMut(&Machine) -> bool,
+    ) -> Result<Status, MachineError> {
+        for _ in 0..n {
+            let status = self.step()?;
+            if status != Status::Running || pause(self) {
+                return Ok(status);
+            }
+        }
+        Ok(if self.is_halted() {
+            Status::Halted
+        } else {
+            Status::Running
+        })
+    }
+
+    /// Adds `amount` to a cell, applying the [`OverflowPolicy`] if it leaves the cell's range.
+    fn add_to_cell(
+        &mut self,
+        index: usize,
+        amount: i128,
+        position: usize,
+    ) -> Result<(), MachineError> {
+        let max = self.cell_width.max_value();
+        let cell = self.tape[index];
+        let sum = i128::from(cell).saturating_add(amount);
+        let value = if (0..=i128::from(max)).contains(&sum) {
+            sum as u64
+        } else {
+            match self.overflow_policy {
+                OverflowPolicy::Wrap => cell.wrapping_add(amount as u64) & max,
+                OverflowPolicy::Saturate if sum < 0 => 0,
+                OverflowPolicy::Saturate => max,
+                OverflowPolicy::Trap => {
+                    return Err(MachineError::CellOverflow {
+                        cell: index as isize - self.origin as isize,
+                        position,
+                    })
+                }
+            }
+        };
+        self.write_cell(index, value);
+        Ok(())
+    }
+
+    /// Stores a value in a cell, remembering the write for [`last_write`](Machine::last_write).
+    fn write_cell(&mut self, index: usize, value: u64) {
+        self.last_write = Some(CellWrite {
+            cell: index as isize - self.origin as isize,
+            old: self.tape[index],
+            new: value,
+        });
+        self.tape[index] = value;
+    }
+
+    fn write_output(&mut self, value: u64) {
+        if self.cell_width == CellWidth::Bits8 {
+            self.output.push(value as u8);
+        } else {
+            let c = u32::try_from(value)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.output
+                .extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+
+    /// Index of the cell `offset` away from the data pointer, growing the tape if needed.
+    fn cell_index(&mut self, offset: isize, position: usize) -> Result<usize, MachineError> {
+        if self.tape_mode == TapeMode::Circular {
+            let len = self.tape.len() as isize;
+            return Ok((self.data_pointer as isize + offset % len).rem_euclid(len) as usize);
+        }
+        let index = match self.data_pointer.checked_add_signed(offset) {
+            Some(index) => index,
+            None if self.tape_mode == TapeMode::Bidirectional => {
+                // Grow the left side at least as much as the tape already is, like `Vec` does
+                let missing = offset.unsigned_abs() - self.data_pointer;
+                let added = missing.max(self.tape.len());
+                self.tape.splice(0..0, std::iter::repeat_n(0, added));
+                self.origin += added;
+                self.data_pointer += added;
+                self.data_pointer - offset.unsigned_abs()
+            }
+            None => return Err(MachineError::OutOfBounds { position }),
+        };
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        Ok(index)
+    }
+
+    fn move_pointer(&mut self, offset: isize, position: usize) -> Result<(), MachineError> {
+        self.data_pointer = self.cell_index(offset, position)?;
+        Ok(())
+    }
+}
@@ -31,6 +31,6 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Brainfuck Interpreter",
         native_options,
-        Box::new(|cc| Ok(Box::new(brain_fuck_interpreter::BrainfuckInterpreterInterface::new(cc)))),
+        Box::new(|cc| Ok(Box::new(brain_fuck_interpreter::BrainfuckInterpreterApp::new(cc)))),
     )
 }
@@ -0,0 +1,185 @@
+use crate::bytecode::{Instruction, Op};
+use crate::machine::{OverflowPolicy, TapeMode};
+
+/// Which optimization passes [`Program::compile_with`](crate::Program::compile_with) runs.
+///
+/// Every pass can be switched off on its own, so results can be compared with
+/// the naive one-instruction-per-command program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Optimizations {
+    /// Fold runs like `+++++` into `Add(5)` and `>>>` into `Move(3)`.
+    /// Runs of `+` and `-` are folded separately, and so are runs of `<` and `>`.
+    pub fold_runs: bool,
+    /// Turn `[-]` and `[+]` into `SetZero`. Only right when cells wrap around.
+    pub set_zero: bool,
+    /// Turn `[->+<]`-style loops into `MulAdd`s followed by `SetZero`. Only right
+    /// when cells wrap around.
+    pub multiply_loops: bool,
+    /// Turn `[>]`-style loops into `Scan`.
+    pub scan_loops: bool,
+}
+
+impl Optimizations {
+    pub fn none() -> Self {
+        Self {
+            fold_runs: false,
+            set_zero: false,
+            multiply_loops: false,
+            scan_loops: false,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            fold_runs: true,
+            set_zero: true,
+            multiply_loops: true,
+            scan_loops: true,
+        }
+    }
+
+    /// These passes without the ones that change what a program does unless cells
+    /// wrap around. Under `Saturate` `[+]` never ends, and under `Trap` loops stop
+    /// at the instruction that overflowed.
+    pub fn for_overflow_policy(self, overflow_policy: OverflowPolicy) -> Self {
+        if overflow_policy == OverflowPolicy::Wrap {
+            return self;
+        }
+        Self {
+            set_zero: false,
+            multiply_loops: false,
+            ..self
+        }
+    }
+
+    /// These passes without the loop rewrites that assume cells at different
+    /// offsets are different cells. On a circular tape an offset of a whole lap
+    /// lands back on the loop's own cell.
+    pub fn for_tape_mode(self, tape_mode: TapeMode) -> Self {
+        if tape_mode != TapeMode::Circular {
+            return self;
+        }
+        Self {
+            multiply_loops: false,
+            scan_loops: false,
+            ..self
+        }
+    }
+}
+
+impl Default for Optimizations {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Runs the enabled passes over a freshly parsed program and re-resolves the jumps.
+pub(crate) fn optimize(
+    instructions: Vec<Instruction>,
+    optimizations: &Optimizations,
+) -> Vec<Instruction> {
+    if *optimizations == Optimizations::none() {
+        return instructions;
+    }
+
+    let mut out: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    let mut open_loops = Vec::new();
+
+    for instruction in instructions {
+        match instruction.op {
+            Op::Add(n) if optimizations.fold_runs => {
+                // `+-` is not folded away, it matters when cells saturate or trap
+                if let Some(Instruction { op: Op::Add(m), .. }) = out.last_mut() {
+                    if m.signum() == n.signum() {
+                        *m += n;
+                        continue;
+                    }
+                }
+            }
+            Op::Move(n) if optimizations.fold_runs => {
+                // `<>` is not folded away either, the `<` can leave the tape
+                if let Some(Instruction {
+                    op: Op::Move(m), ..
+                }) = out.last_mut()
+                {
+                    if m.signum() == n.signum() {
+                        *m += n;
+                        continue;
+                    }
+                }
+            }
+            Op::JumpIfZero(_) => open_loops.push(out.len()),
+            Op::JumpIfNotZero(_) => {
+                let start = open_loops.pop().expect("brackets are balanced");
+                if let Some(replacement) = simplify_loop(&out[start + 1..], optimizations) {
+                    let position = out[start].position;
+                    out.truncate(start);
+                    out.extend(
+                        replacement
+                            .into_iter()
+                            .map(|op| Instruction { op, position }),
+                    );
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(instruction);
+    }
+
+    link_jumps(&mut out);
+    out
+}
+
+/// Recognizes loop bodies that only add and move and have a closed form.
+fn simplify_loop(body: &[Instruction], optimizations: &Optimizations) -> Option<Vec<Op>> {
+    let mut offset: isize = 0;
+    let mut deltas: Vec<(isize, i64)> = Vec::new();
+    for instruction in body {
+        match instruction.op {
+            Op::Move(n) => offset += n,
+            Op::Add(n) => match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta += n,
+                None => deltas.push((offset, n)),
+            },
+            _ => return None,
+        }
+    }
+    deltas.retain(|&(_, delta)| delta != 0);
+
+    if deltas.is_empty() {
+        return (optimizations.scan_loops && offset != 0).then(|| vec![Op::Scan(offset)]);
+    }
+    if offset != 0 {
+        return None;
+    }
+    if optimizations.set_zero && matches!(deltas.as_slice(), [(0, 1 | -1)]) {
+        return Some(vec![Op::SetZero]);
+    }
+    // A multiply loop must decrement its own cell by exactly one per iteration
+    if !optimizations.multiply_loops || deltas.len() < 2 || !deltas.contains(&(0, -1)) {
+        return None;
+    }
+    let mut ops: Vec<Op> = deltas
+        .into_iter()
+        .filter(|&(offset, _)| offset != 0)
+        .map(|(offset, factor)| Op::MulAdd { offset, factor })
+        .collect();
+    ops.push(Op::SetZero);
+    Some(ops)
+}
+
+fn link_jumps(instructions: &mut [Instruction]) {
+    let mut open_loops = Vec::new();
+    for i in 0..instructions.len() {
+        match instructions[i].op {
+            Op::JumpIfZero(_) => open_loops.push(i),
+            Op::JumpIfNotZero(_) => {
+                let start = open_loops.pop().expect("brackets are balanced");
+                instructions[start].op = Op::JumpIfZero(i + 1);
+                instructions[i].op = Op::JumpIfNotZero(start + 1);
+            }
+            _ => {}
+        }
+    }
+}
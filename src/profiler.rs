@@ -0,0 +1,184 @@
+use crate::bytecode::{Op, Program};
+use std::fmt::Write;
+
+/// Execution counts collected by a machine created with
+/// [`with_profiling`](crate::Machine::with_profiling).
+///
+/// Counts are per compiled instruction. With optimizations on, a folded run
+/// like `+++` or a simplified loop like `[->+<]` counts as one instruction at
+/// the position of its first character, and simplified loops are not listed in
+/// [`loops`](Profile::loops). Profile without optimizations for exact per-command counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Source position of every instruction.
+    positions: Vec<usize>,
+    /// Times every instruction ran.
+    hits: Vec<u64>,
+    /// Instruction indices of every `[` and its matching `]`, in source order.
+    loop_instructions: Vec<(usize, usize)>,
+    total: u64,
+    max_cell: isize,
+}
+
+/// Execution counts of one loop, see [`Profile::loops`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopProfile {
+    /// Character index of the `[`.
+    pub start: usize,
+    /// Character index of the matching `]`.
+    pub end: usize,
+    /// Times the `[` was reached.
+    pub entries: u64,
+    /// Times the body ran, that is times the `]` was reached.
+    pub iterations: u64,
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        let instructions = program.instructions();
+        let loop_instructions = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instruction)| match instruction.op {
+                // The jump target is the instruction after the matching `]`
+                Op::JumpIfZero(after_end) => Some((i, after_end - 1)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            positions: instructions
+                .iter()
+                .map(|instruction| instruction.position)
+                .collect(),
+            hits: vec![0; instructions.len()],
+            loop_instructions,
+            total: 0,
+            max_cell: 0,
+        }
+    }
+
+    /// Counts one execution of instruction `index` that touched the logical `cell`.
+    pub(crate) fn record(&mut self, index: usize, cell: isize) {
+        self.hits[index] += 1;
+        self.total += 1;
+        self.max_cell = self.max_cell.max(cell);
+    }
+
+    /// Takes back one [`record`](Profile::record) of instruction `index` when the
+    /// machine steps back, restoring the highest cell seen before it.
+    pub(crate) fn unrecord(&mut self, index: usize, max_cell: isize) {
+        self.hits[index] -= 1;
+        self.total -= 1;
+        self.max_cell = max_cell;
+    }
+
+    /// Times each instruction ran, indexed like [`Program::instructions`].
+    pub fn instruction_hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// Total number of instructions executed while profiling.
+    pub fn total_instructions(&self) -> u64 {
+        self.total
+    }
+
+    /// Highest logical cell index the pointer or a write reached.
+    pub fn max_cell(&self) -> isize {
+        self.max_cell
+    }
+
+    /// Execution counts by source position, for every position an instruction starts at.
+    pub fn position_hits(&self) -> Vec<(usize, u64)> {
+        let mut hits: Vec<(usize, u64)> = Vec::with_capacity(self.positions.len());
+        for (&position, &count) in self.positions.iter().zip(&self.hits) {
+            // A simplified loop is several instructions at the `[`, which all run together
+            if hits.last().is_some_and(|&(last, _)| last == position) {
+                continue;
+            }
+            hits.push((position, count));
+        }
+        hits
+    }
+
+    /// Execution counts of every loop that is still a loop after optimization.
+    pub fn loops(&self) -> Vec<LoopProfile> {
+        self.loop_instructions
+            .iter()
+            .map(|&(start, end)| LoopProfile {
+                start: self.positions[start],
+                end: self.positions[end],
+                entries: self.hits[start],
+                iterations: self.hits[end],
+            })
+            .collect()
+    }
+
+    /// Times the code at each character ran, for `source_len` characters. Every
+    /// instruction covers the characters up to the next one, so the count of a folded
+    /// run or simplified loop is spread over all of its characters.
+    pub fn character_hits(&self, source_len: usize) -> Vec<u64> {
+        let mut hits = vec![0; source_len];
+        for (i, &count) in self.hits.iter().enumerate() {
+            let start = self.positions[i].min(source_len);
+            let end = self
+                .positions
+                .get(i + 1)
+                .map_or(source_len, |&next| next.min(source_len));
+            hits[start..end.max(start)].fill(count);
+        }
+        hits
+    }
+
+    /// The report as JSON, with the counts by position and the loops.
+    pub fn to_json(&self) -> String {
+        let positions: Vec<String> = self
+            .position_hits()
+            .iter()
+            .map(|(position, hits)| format!("{{\"position\":{position},\"hits\":{hits}}}"))
+            .collect();
+        let loops: Vec<String> = self
+            .loops()
+            .iter()
+            .map(|l| {
+                format!(
+                    "{{\"start\":{},\"end\":{},\"entries\":{},\"iterations\":{}}}",
+                    l.start, l.end, l.entries, l.iterations
+                )
+            })
+            .collect();
+        format!(
+            "{{\"total_instructions\":{},\"max_cell\":{},\"positions\":[{}],\"loops\":[{}]}}\n",
+            self.total,
+            self.max_cell,
+            positions.join(","),
+            loops.join(",")
+        )
+    }
+
+    /// The report as text for people: the totals, then the loops and the
+    /// positions that ran most often first.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "Total instructions: {}", self.total);
+        let _ = writeln!(text, "Highest cell: {}", self.max_cell);
+
+        let mut loops = self.loops();
+        loops.sort_by_key(|l| std::cmp::Reverse(l.iterations));
+        let _ = writeln!(text, "\nLoops (characters, entries, iterations):");
+        for l in loops {
+            let _ = writeln!(
+                text,
+                "  {:>6}..{:<6} {:>12} {:>12}",
+                l.start, l.end, l.entries, l.iterations
+            );
+        }
+
+        let mut positions = self.position_hits();
+        positions.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+        let _ = writeln!(text, "\nInstructions (character, hits):");
+        for (position, hits) in positions {
+            let _ = writeln!(text, "  {position:>6} {hits:>12}");
+        }
+        text
+    }
+}
@@ -1,9 +1,13 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use std::sync::{Arc, Mutex};
     use std::thread;
     use std::time::Duration;
-    use crate::BrainfuckInterpreterInterface;
+    use crate::{
+        text_to_brainfuck, to_c, to_elf, to_rust, to_wasm, to_wat, BrainfuckInterpreterApp, BrainfuckInterpreterInterface, CellWidth, CellWrite, Condition, Coverage, EofBehavior, Instruction, Machine, MachineError,
+        Op, Optimizations, OverflowPolicy, Program, Status, TapeMode, TranspileOptions, WatchKind, Watchpoint, LoopProfile,
+    };
     
     #[test]
     fn test_interpreter_run() {
@@ -30,6 +34,20 @@ mod tests {
         assert_eq!(*interface.output.lock().unwrap(), "A".to_string());
     }
 
+    #[test]
+    fn test_interpreter_default_eof_behavior() {
+        // Same as the library and `bf`: `,` past the end of the input leaves the cell alone
+        let mut interface = BrainfuckInterpreterInterface::default();
+        assert_eq!(interface.eof_behavior, EofBehavior::default());
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = "+,.".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "\u{01}");
+    }
+
     #[test]
     fn test_nested_loops() {
         let mut interface = BrainfuckInterpreterInterface::default();
@@ -52,7 +70,7 @@ mod tests {
         if let Some(handle) = interface.timer_thread_handle.take() {
             handle.join().unwrap();
         }
-        assert_eq!(*interface.timer_running.lock().unwrap(), false);
+        assert!(!*interface.timer_running.lock().unwrap());
     }
     
     #[test]
@@ -115,4 +133,1451 @@ mod tests {
             handle.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_machine_run() {
+        let mut machine = Machine::new("++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.").unwrap();
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.output(), b"Hello World!\n");
+    }
+
+    #[test]
+    fn test_machine_step_and_run_for() {
+        let mut machine = Machine::new("+ comment +>+").unwrap();
+        assert_eq!(machine.step(), Ok(Status::Running));
+        assert_eq!(machine.tape()[0], 1);
+        assert_eq!(machine.run_for(2), Ok(Status::Running));
+        assert_eq!((machine.tape()[0], machine.data_pointer()), (2, 1));
+        assert_eq!(machine.run_for(10), Ok(Status::Halted));
+        assert_eq!(machine.tape()[1], 1);
+    }
+
+    #[test]
+    fn test_machine_input() {
+        let mut machine = Machine::new(",.,.").unwrap();
+        machine.push_input(b"A");
+        assert_eq!(machine.run(), Ok(Status::AwaitingInput));
+        machine.push_input(b"B");
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.take_output(), b"AB");
+    }
+
+    #[test]
+    fn test_machine_errors() {
+        assert_eq!(Machine::new("[[]").unwrap_err(), MachineError::UnbalancedBrackets);
+        let mut machine = Machine::new("+<").unwrap();
+        assert_eq!(machine.run(), Err(MachineError::OutOfBounds { position: 1 }));
+    }
+
+    #[test]
+    fn test_program_jump_table() {
+        let program = Program::compile("a+[b>[-]<]").unwrap();
+        let ops: Vec<Op> = program.instructions().iter().map(|i| i.op).collect();
+        assert_eq!(
+            ops,
+            vec![
+                Op::Add(1),
+                Op::JumpIfZero(8),
+                Op::Move(1),
+                Op::JumpIfZero(6),
+                Op::Add(-1),
+                Op::JumpIfNotZero(4),
+                Op::Move(-1),
+                Op::JumpIfNotZero(2),
+            ]
+        );
+        assert_eq!(
+            program.instructions()[2],
+            Instruction {
+                op: Op::Move(1),
+                position: 4
+            }
+        );
+        assert_eq!(program.source_len(), 10);
+        assert_eq!(Program::compile("]["), Err(MachineError::UnbalancedBrackets));
+    }
+
+    #[test]
+    fn test_machine_source_position() {
+        let mut machine = Machine::new("+ comment +").unwrap();
+        assert_eq!(machine.source_position(), 0);
+        machine.step().unwrap();
+        assert_eq!(machine.source_position(), 10);
+        machine.step().unwrap();
+        assert_eq!(machine.source_position(), 11);
+    }
+
+    #[test]
+    fn test_optimizer_idioms() {
+        let program = Program::compile_with("+++++>>>[-]<<[->+>++<<][>]", &Optimizations::all()).unwrap();
+        let ops: Vec<Op> = program.instructions().iter().map(|i| i.op).collect();
+        assert_eq!(
+            ops,
+            vec![
+                Op::Add(5),
+                Op::Move(3),
+                Op::SetZero,
+                Op::Move(-2),
+                Op::MulAdd {
+                    offset: 1,
+                    factor: 1
+                },
+                Op::MulAdd {
+                    offset: 2,
+                    factor: 2
+                },
+                Op::SetZero,
+                Op::Scan(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_optimizer_passes_toggle() {
+        let only_fold = Optimizations {
+            fold_runs: true,
+            ..Optimizations::none()
+        };
+        let program = Program::compile_with("++[-]", &only_fold).unwrap();
+        let ops: Vec<Op> = program.instructions().iter().map(|i| i.op).collect();
+        assert_eq!(
+            ops,
+            vec![Op::Add(2), Op::JumpIfZero(4), Op::Add(-1), Op::JumpIfNotZero(2)]
+        );
+    }
+
+    #[test]
+    fn test_optimizer_same_output() {
+        let sources = [
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+            include_str!("../hello.bf"),
+        ];
+        for source in sources {
+            let mut expected = Machine::new(source).unwrap();
+            expected.run().unwrap();
+            for bits in 0..16 {
+                let optimizations = Optimizations {
+                    fold_runs: bits & 1 != 0,
+                    set_zero: bits & 2 != 0,
+                    multiply_loops: bits & 4 != 0,
+                    scan_loops: bits & 8 != 0,
+                };
+                let program = Program::compile_with(source, &optimizations).unwrap();
+                let mut machine = Machine::from_program(program);
+                assert_eq!(machine.run(), Ok(Status::Halted));
+                assert_eq!(machine.output(), expected.output(), "{optimizations:?}");
+                assert_eq!(machine.tape(), expected.tape(), "{optimizations:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimizer_keeps_opposite_moves() {
+        for source in ["<>.", "+<>>"] {
+            let mut expected = Machine::from_program(Program::compile_with(source, &Optimizations::none()).unwrap());
+            let mut machine = Machine::from_program(Program::compile_with(source, &Optimizations::all()).unwrap());
+            let result = expected.run();
+            assert!(matches!(result, Err(MachineError::OutOfBounds { .. })), "{source}");
+            assert_eq!(machine.run(), result, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_machine_eof_behavior() {
+        for (eof_behavior, expected) in [
+            (EofBehavior::Unchanged, 7),
+            (EofBehavior::Zero, 0),
+            (EofBehavior::MinusOne, 255),
+        ] {
+            let mut machine = Machine::new("+++++++,").unwrap().with_eof_behavior(eof_behavior);
+            assert_eq!(machine.run(), Ok(Status::AwaitingInput));
+            machine.close_input();
+            assert_eq!(machine.run(), Ok(Status::Halted));
+            assert_eq!(machine.tape()[0], expected);
+        }
+    }
+
+    #[test]
+    fn test_machine_instructions_executed() {
+        let mut machine = Machine::new("++[-]").unwrap();
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.instructions_executed(), 7);
+    }
+
+    #[test]
+    fn test_machine_cell_width() {
+        for (width, expected) in [
+            (CellWidth::Bits8, 255),
+            (CellWidth::Bits16, 65535),
+            (CellWidth::Bits32, u32::MAX as u64),
+            (CellWidth::Bits64, u64::MAX),
+        ] {
+            let mut machine = Machine::new("-").unwrap().with_cell_width(width);
+            machine.run().unwrap();
+            assert_eq!(machine.tape()[0], expected, "{width}");
+            let mut machine = Machine::new("-+").unwrap().with_cell_width(width);
+            machine.run().unwrap();
+            assert_eq!(machine.tape()[0], 0, "{width}");
+        }
+
+        // 256 only fits in cells wider than 8 bits
+        let source = "++++++++++++++++[>++++++++++++++++<-]>[[-]+.-]";
+        let mut machine = Machine::new(source).unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.output(), b"");
+        let mut machine = Machine::new(source).unwrap().with_cell_width(CellWidth::Bits16);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), b"\x01");
+    }
+
+    #[test]
+    fn test_machine_wide_cell_output() {
+        let mut machine = Machine::new(",.").unwrap().with_cell_width(CellWidth::Bits16);
+        machine.push_input_char('€');
+        machine.run().unwrap();
+        assert_eq!(machine.output(), "€".as_bytes());
+
+        let mut machine = Machine::new(",.").unwrap();
+        machine.push_input_char('é');
+        machine.run().unwrap();
+        assert_eq!(machine.output(), [0xE9]);
+    }
+
+    #[test]
+    fn test_interpreter_cell_width() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.cell_width = CellWidth::Bits16;
+        *interface.input_brainfuck.lock().unwrap() = "-.".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(interface.data.lock().unwrap()[0], 65535);
+        assert_eq!(*interface.output.lock().unwrap(), "\u{FFFF}".to_string());
+    }
+
+    #[test]
+    fn test_machine_overflow_policy() {
+        for optimizations in [Optimizations::none(), Optimizations::all()] {
+            let program = Program::compile_with("--+>+++[->+++<]", &optimizations).unwrap();
+            let mut machine = Machine::from_program(program.clone());
+            machine.run().unwrap();
+            assert_eq!(&machine.tape()[..3], [255, 0, 9]);
+
+            let mut machine = Machine::from_program(program.clone())
+                .with_overflow_policy(OverflowPolicy::Saturate);
+            machine.run().unwrap();
+            assert_eq!(&machine.tape()[..3], [1, 0, 9]);
+
+            let mut machine =
+                Machine::from_program(program).with_overflow_policy(OverflowPolicy::Trap);
+            assert_eq!(
+                machine.run(),
+                Err(MachineError::CellOverflow {
+                    cell: 0,
+                    position: 0
+                })
+            );
+        }
+
+        let mut machine = Machine::new("++++++++++++++++[>++++++++++++++++<-]")
+            .unwrap()
+            .with_overflow_policy(OverflowPolicy::Trap);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error, MachineError::CellOverflow { cell: 1, position: 33 });
+        assert_eq!(error.to_string(), "Cell 1 overflowed at instruction 33");
+
+        // Loops that only clear or move cells when they wrap around are kept as loops
+        let multiply = format!("{}>++++++++++[-<+>]", "+".repeat(250));
+        for source in ["+[+]", multiply.as_str()] {
+            for policy in [OverflowPolicy::Saturate, OverflowPolicy::Trap] {
+                let optimizations = Optimizations::all().for_overflow_policy(policy);
+                let mut expected = Machine::from_program(Program::compile_with(source, &Optimizations::none()).unwrap())
+                    .with_overflow_policy(policy);
+                let mut machine = Machine::from_program(Program::compile_with(source, &optimizations).unwrap())
+                    .with_overflow_policy(policy);
+                let result = expected.run_for(10_000);
+                assert_eq!(machine.run_for(10_000), result, "{source} {policy}");
+                assert_eq!(machine.tape(), expected.tape(), "{source} {policy}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpreter_overflow_trap() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.overflow_policy = OverflowPolicy::Trap;
+        *interface.input_brainfuck.lock().unwrap() = ">-".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(*interface.warn.lock().unwrap());
+        assert_eq!(
+            *interface.warn_message.lock().unwrap(),
+            "Cell 1 overflowed at instruction 1"
+        );
+        assert!(!*interface.timer_running.lock().unwrap());
+    }
+
+    #[test]
+    fn test_machine_eof_error_and_block() {
+        let mut machine = Machine::new("+,").unwrap().with_eof_behavior(EofBehavior::Error);
+        assert_eq!(machine.run(), Ok(Status::AwaitingInput));
+        machine.close_input();
+        assert_eq!(machine.run(), Err(MachineError::EndOfInput { position: 1 }));
+
+        let mut machine = Machine::new("+,").unwrap().with_eof_behavior(EofBehavior::Block);
+        assert_eq!(machine.run(), Ok(Status::AwaitingInput));
+        assert_eq!(machine.run(), Ok(Status::AwaitingInput));
+        machine.push_input(b"x");
+        assert_eq!(machine.run(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn test_interpreter_eof_behavior() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.eof_behavior = EofBehavior::Zero;
+        *interface.input_brainfuck.lock().unwrap() = ",[.,]".to_string();
+        *interface.input_text.lock().unwrap() = "hi".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "hi".to_string());
+        assert!(interface.input_text.lock().unwrap().is_empty());
+
+        interface.eof_behavior = EofBehavior::Error;
+        *interface.input_brainfuck.lock().unwrap() = ",".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            *interface.warn_message.lock().unwrap(),
+            "Tried to read past the end of input"
+        );
+    }
+
+    #[test]
+    fn test_machine_bidirectional_tape() {
+        for optimizations in [Optimizations::none(), Optimizations::all()] {
+            let program = Program::compile_with("<<+++<-[>]>+", &optimizations).unwrap();
+            let mut machine = Machine::from_program(program)
+                .with_tape_size(4)
+                .with_tape_mode(TapeMode::Bidirectional);
+            assert_eq!(machine.run(), Ok(Status::Halted));
+            let origin = machine.origin();
+            assert_eq!(machine.tape()[origin - 3], 255);
+            assert_eq!(machine.tape()[origin - 2], 3);
+            assert_eq!(machine.tape()[origin], 1);
+            assert_eq!(machine.data_pointer(), origin);
+        }
+
+        let mut machine = Machine::new("<-")
+            .unwrap()
+            .with_tape_mode(TapeMode::Bidirectional)
+            .with_overflow_policy(OverflowPolicy::Trap);
+        assert_eq!(
+            machine.run(),
+            Err(MachineError::CellOverflow {
+                cell: -1,
+                position: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_machine_circular_tape() {
+        let mut machine = Machine::new("<+>>>+>>+")
+            .unwrap()
+            .with_tape_size(3)
+            .with_tape_mode(TapeMode::Circular);
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.tape(), [0, 1, 2]);
+        assert_eq!(machine.data_pointer(), 1);
+    }
+
+    #[test]
+    fn test_optimizer_circular_tape() {
+        // Offsets of a whole lap land on the loop's own cell
+        for (source, size) in [("++[->>+<<]", 2), ("++[->>>+<<<]", 3), ("+>+[>>]", 2), ("++[->+<]>.", 3)] {
+            let optimizations = Optimizations::all().for_tape_mode(TapeMode::Circular);
+            let mut expected = Machine::from_program(Program::compile_with(source, &Optimizations::none()).unwrap())
+                .with_tape_size(size)
+                .with_tape_mode(TapeMode::Circular);
+            let mut machine = Machine::from_program(Program::compile_with(source, &optimizations).unwrap())
+                .with_tape_size(size)
+                .with_tape_mode(TapeMode::Circular);
+            let result = expected.run_for(10_000);
+            assert_eq!(machine.run_for(10_000), result, "{source}");
+            // Folded runs take fewer steps, so only finished runs end on the same tape
+            if result == Ok(Status::Halted) {
+                assert_eq!(machine.tape(), expected.tape(), "{source}");
+                assert_eq!(machine.output(), expected.output(), "{source}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_machine_scan_circular_tape_without_zero() {
+        for optimizations in [Optimizations::all(), Optimizations::none()] {
+            let program = Program::compile_with("+>+>+[>]", &optimizations).unwrap();
+            let mut machine = Machine::from_program(program)
+                .with_tape_size(3)
+                .with_tape_mode(TapeMode::Circular);
+            assert_eq!(machine.run_for(1000), Ok(Status::Running));
+            assert_eq!(machine.instructions_executed(), 1000);
+        }
+    }
+
+    #[test]
+    fn test_interpreter_bidirectional_tape() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.tape_mode = TapeMode::Bidirectional;
+        *interface.input_brainfuck.lock().unwrap() = "<<<<".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(!*interface.warn.lock().unwrap());
+        let origin = *interface.tape_origin.lock().unwrap();
+        assert_eq!(*interface.box_index.lock().unwrap() + 4, origin);
+        assert_eq!(interface.data.lock().unwrap().len(), 512);
+
+        // The next run starts from the configured size again, not the grown tape
+        interface.tape_mode = TapeMode::Standard;
+        *interface.input_brainfuck.lock().unwrap() = "+".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(interface.data.lock().unwrap().len(), 256);
+    }
+
+    #[test]
+    fn test_interpreter_step() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        *interface.input_brainfuck.lock().unwrap() = "+>++".to_string();
+        interface.optimize = false;
+        interface.step_interpreter();
+        assert!(interface.is_paused());
+        assert_eq!(interface.data.lock().unwrap()[0], 1);
+        assert_eq!(*interface.letter_index.lock().unwrap(), 1);
+        interface.step_interpreter();
+        interface.step_interpreter();
+        assert_eq!(*interface.box_index.lock().unwrap(), 1);
+        assert_eq!(interface.data.lock().unwrap()[1], 1);
+        interface.step_interpreter();
+        assert!(!interface.is_paused());
+        assert_eq!(interface.data.lock().unwrap()[1], 2);
+    }
+
+    #[test]
+    fn test_interpreter_step_over_loop() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.optimize = false;
+        *interface.input_brainfuck.lock().unwrap() = "+++[>++<-]>.".to_string();
+        for _ in 0..3 {
+            interface.step_interpreter();
+        }
+        assert_eq!(*interface.letter_index.lock().unwrap(), 3);
+        interface.step_over_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert_eq!(*interface.letter_index.lock().unwrap(), 10);
+        assert_eq!(interface.data.lock().unwrap()[..2], [0, 6]);
+    }
+
+    #[test]
+    fn test_interpreter_run_to_position_and_resume() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = "++[>+<-] comment >.+.".to_string();
+        interface.run_to_position(18);
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert_eq!(*interface.letter_index.lock().unwrap(), 18);
+        assert_eq!(interface.data.lock().unwrap()[..2], [0, 2]);
+        assert!(interface.output.lock().unwrap().is_empty());
+
+        interface.resume_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(!interface.is_paused());
+        assert_eq!(*interface.output.lock().unwrap(), "\u{2}\u{3}");
+    }
+
+    #[test]
+    fn test_interpreter_pause_keeps_state() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.cell_width = CellWidth::Bits64; // So the counter never wraps back to 0
+        *interface.input_brainfuck.lock().unwrap() = "+[>+<]".to_string();
+        interface.start_interpreter();
+        thread::sleep(Duration::from_millis(50));
+        interface.pause_interpreter();
+        assert!(interface.is_paused());
+        let counted = interface.data.lock().unwrap()[1];
+        assert!(counted > 0);
+
+        interface.resume_interpreter();
+        thread::sleep(Duration::from_millis(50));
+        interface.pause_interpreter();
+        assert!(interface.data.lock().unwrap()[1] != counted);
+
+        interface.stop_interpreter();
+        assert!(!interface.is_paused());
+    }
+
+    #[test]
+    fn test_machine_dump() {
+        assert_eq!(Program::compile("+#+").unwrap().len(), 2);
+        let program = Program::compile_with_dumps("++>+++#<.#", &Optimizations::all()).unwrap();
+        let mut machine = Machine::from_program(program);
+        assert_eq!(machine.run(), Ok(Status::Dump));
+        assert_eq!(machine.dump(), "cell 1: 2 [3]");
+        assert_eq!(machine.run(), Ok(Status::Dump));
+        assert_eq!(machine.dump(), "cell 0: [2] 3");
+        assert_eq!(machine.run(), Ok(Status::Halted));
+        assert_eq!(machine.output(), [2]);
+    }
+
+    #[test]
+    fn test_interpreter_breakpoints() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.optimize = false;
+        *interface.input_brainfuck.lock().unwrap() = "+++[>+<-] >.".to_string();
+        interface.breakpoints.insert(String::new(), [5, 9].into());
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert!(*interface.scroll_to_pointer.lock().unwrap());
+        assert_eq!(*interface.letter_index.lock().unwrap(), 5);
+        assert_eq!(interface.data.lock().unwrap()[..2], [3, 0]);
+
+        // The loop body runs twice more before the comment breakpoint is reached
+        for expected in [[2, 1], [1, 2]] {
+            interface.resume_interpreter();
+            if let Some(handle) = interface.timer_thread_handle.take() {
+                handle.join().unwrap();
+            }
+            assert_eq!(interface.data.lock().unwrap()[..2], expected);
+        }
+        interface.resume_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.letter_index.lock().unwrap(), 10);
+        assert_eq!(interface.data.lock().unwrap()[..2], [0, 3]);
+    }
+
+    #[test]
+    fn test_interpreter_dump_breaks() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = "+#+#+".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(!interface.is_paused());
+        assert_eq!(interface.data.lock().unwrap()[0], 3);
+
+        interface.debug_dumps = true;
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert_eq!(interface.data.lock().unwrap()[0], 1);
+        assert_eq!(*interface.letter_index.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut machine = Machine::new(">+++>>>-").unwrap();
+        machine.run_for(4).unwrap();
+        let is_true = |source: &str| Condition::parse(source).unwrap().is_true(&machine);
+        assert!(is_true("cell[1] == 3 && ptr > 0"));
+        assert!(is_true("cell == 3"));
+        assert!(is_true("cell[ptr - 1] == 0 || cell[1000] == 1"));
+        assert!(is_true("!(steps < 4) && cell[-1] == 0"));
+        assert!(is_true("1 + 2 * 3 == 7 && 7 % 4 == 3 && 1 / 0 == 0"));
+        assert!(!is_true("cell[1] != 3"));
+
+        assert!(Condition::parse("cell[1 == 3").is_err());
+        let error = Condition::parse("ptr > foo").unwrap_err();
+        assert_eq!(error.position, 6);
+        assert_eq!(error.to_string(), "Unknown name `foo` at character 6");
+    }
+
+    #[test]
+    fn test_machine_last_write() {
+        let mut machine = Machine::new("+>[-]<.").unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.last_write(), Some(CellWrite { cell: 0, old: 0, new: 1 }));
+        assert_eq!(machine.last_position(), Some(0));
+        machine.step().unwrap();
+        assert_eq!(machine.last_write(), None);
+
+        let program = Program::compile_with("+>[-]<.", &Optimizations::all()).unwrap();
+        let mut machine = Machine::from_program(program);
+        machine.run_for(3).unwrap();
+        assert_eq!(machine.last_write(), Some(CellWrite { cell: 1, old: 0, new: 0 }));
+
+        let watch = |kind| Watchpoint { cell: 1, kind };
+        let write = machine.last_write().unwrap();
+        assert!(watch(WatchKind::Write).is_triggered_by(&write));
+        assert!(!watch(WatchKind::Change).is_triggered_by(&write));
+    }
+
+    #[test]
+    fn test_interpreter_watchpoint_and_condition() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = "++++[>++<-]>[>+<-]".to_string();
+        interface.watchpoints.push(Watchpoint { cell: 2, kind: WatchKind::Change });
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert_eq!(
+            *interface.pause_reason.lock().unwrap(),
+            "Cell 2 changed from 0 to 8 at character 12"
+        );
+
+        interface.watchpoints.clear();
+        interface.optimize = false;
+        interface.break_condition = "cell[1] == 6 && ptr == 1".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.is_paused());
+        assert_eq!(interface.data.lock().unwrap()[..2], [2, 6]);
+        assert_eq!(
+            *interface.pause_reason.lock().unwrap(),
+            "`cell[1] == 6 && ptr == 1` became true at character 7"
+        );
+
+        interface.break_condition = "cell[".to_string();
+        interface.resume_interpreter();
+        assert!(interface.timer_thread_handle.is_none());
+        assert!(*interface.warn.lock().unwrap());
+    }
+
+    #[test]
+    fn test_machine_step_back() {
+        let source = "<<+++[->>++<<]>,.[->+<]>>>>>+.";
+        let program = Program::compile_with(source, &Optimizations::all()).unwrap();
+        let mut machine = Machine::from_program(program)
+            .with_tape_size(2)
+            .with_tape_mode(TapeMode::Bidirectional)
+            .with_history(1000);
+        machine.push_input(b"A");
+
+        let snapshot = |machine: &Machine| {
+            (
+                machine.tape().to_vec(),
+                machine.origin(),
+                machine.data_pointer(),
+                machine.instruction_pointer(),
+                machine.instructions_executed(),
+            )
+        };
+        let mut snapshots = vec![snapshot(&machine)];
+        while machine.step().unwrap() != Status::Halted {
+            snapshots.push(snapshot(&machine));
+        }
+        assert_eq!(machine.take_output(), [65, 1]);
+
+        let mut taken_output = 0;
+        let mut input = Vec::new();
+        while let Some(rewound) = machine.step_back() {
+            assert_eq!(snapshot(&machine), snapshots.pop().unwrap());
+            taken_output += rewound.taken_output;
+            input.extend(rewound.input);
+        }
+        assert!(snapshots.is_empty());
+        assert_eq!(taken_output, 2);
+        assert_eq!(input, [65]);
+        assert_eq!(machine.take_input(), [65]);
+        assert_eq!(machine.last_position(), None);
+    }
+
+    #[test]
+    fn test_machine_history_limit() {
+        let mut machine = Machine::new("+++++").unwrap().with_history(2);
+        machine.run().unwrap();
+        assert_eq!(machine.history_len(), 2);
+        assert!(machine.step_back().is_some());
+        assert!(machine.step_back().is_some());
+        assert!(machine.step_back().is_none());
+        assert_eq!(machine.tape()[0], 3);
+        assert_eq!(machine.last_position(), None);
+
+        let mut machine = Machine::new("+.").unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.history_len(), 0);
+    }
+
+    #[test]
+    fn test_machine_step_back_profile() {
+        let source = "++[>+++[>+<-]<-]";
+        let mut straight = Machine::new(source).unwrap().with_profiling();
+        straight.run().unwrap();
+
+        let mut machine = Machine::new(source).unwrap().with_profiling().with_history(1000);
+        machine.run_for(20).unwrap();
+        while machine.step_back().is_some() {}
+        assert_eq!(machine.profile().unwrap().total_instructions(), 0);
+        assert_eq!(machine.profile().unwrap().max_cell(), 0);
+        machine.run().unwrap();
+        assert_eq!(machine.profile(), straight.profile());
+    }
+
+    #[test]
+    fn test_interpreter_step_back() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = ",.,.+.".to_string();
+        *interface.input_text.lock().unwrap() = "ab".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "abc");
+        assert!(interface.input_text.lock().unwrap().is_empty());
+        assert!(interface.can_step_back());
+
+        interface.step_back_interpreter();
+        interface.step_back_interpreter();
+        assert!(interface.is_paused());
+        assert_eq!(*interface.output.lock().unwrap(), "ab");
+        assert_eq!(*interface.letter_index.lock().unwrap(), 4);
+        interface.step_back_interpreter();
+        assert_eq!(*interface.output.lock().unwrap(), "a");
+        interface.step_back_interpreter();
+        assert_eq!(*interface.input_text.lock().unwrap(), "b");
+        assert_eq!(*interface.letter_index.lock().unwrap(), 2);
+
+        interface.breakpoints.insert(String::new(), [1].into());
+        interface.run_back_interpreter();
+        assert_eq!(*interface.letter_index.lock().unwrap(), 1);
+        assert_eq!(*interface.pause_reason.lock().unwrap(), "Breakpoint at character 1");
+        interface.run_back_interpreter();
+        assert_eq!(*interface.letter_index.lock().unwrap(), 0);
+        assert_eq!(*interface.input_text.lock().unwrap(), "ab");
+        assert!(interface.output.lock().unwrap().is_empty());
+        assert!(!interface.can_step_back());
+
+        interface.resume_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.letter_index.lock().unwrap(), 1);
+        assert!(interface.output.lock().unwrap().is_empty());
+        interface.breakpoints.clear();
+        interface.resume_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_interpreter_step_back_wide_output_and_eof() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.cell_width = CellWidth::Bits32;
+        interface.eof_behavior = EofBehavior::Zero;
+        *interface.input_brainfuck.lock().unwrap() = ",.,.".to_string();
+        *interface.input_text.lock().unwrap() = "é".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "é\0");
+
+        interface.step_back_interpreter();
+        assert_eq!(*interface.output.lock().unwrap(), "é");
+        interface.step_back_interpreter();
+        let input_closed = |interface: &BrainfuckInterpreterInterface| {
+            let machine = interface.machine.lock().unwrap();
+            machine.as_ref().unwrap().is_input_closed()
+        };
+        assert!(!input_closed(&interface));
+        interface.step_back_interpreter();
+        assert!(interface.output.lock().unwrap().is_empty());
+        interface.step_back_interpreter();
+        assert_eq!(*interface.input_text.lock().unwrap(), "é");
+
+        interface.input_text.lock().unwrap().push('z');
+        interface.resume_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "éz");
+        assert!(interface.input_text.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut machine = Machine::new("++[>+++[>+<-]<-] end").unwrap().with_profiling();
+        machine.run().unwrap();
+        let profile = machine.profile().unwrap();
+        assert_eq!(profile.total_instructions(), machine.instructions_executed());
+        assert_eq!(profile.max_cell(), 2);
+        assert_eq!(
+            profile.loops(),
+            [
+                LoopProfile { start: 2, end: 15, entries: 1, iterations: 2 },
+                LoopProfile { start: 7, end: 12, entries: 2, iterations: 6 },
+            ]
+        );
+        assert_eq!(profile.position_hits()[..3], [(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(profile.position_hits()[8], (8, 6));
+
+        let json = profile.to_json();
+        assert!(json.starts_with("{\"total_instructions\":"));
+        assert!(json.contains("{\"start\":7,\"end\":12,\"entries\":2,\"iterations\":6}"));
+        let text = profile.to_text();
+        assert!(text.contains("Highest cell: 2"));
+
+        // A folded run spreads its count over every character, up to the next instruction
+        let program = Program::compile_with("+++[->+<]>.", &Optimizations::all()).unwrap();
+        let mut machine = Machine::from_program(program).with_profiling();
+        machine.run().unwrap();
+        let hits = machine.profile().unwrap().character_hits(11);
+        assert_eq!(hits, [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(machine.profile().unwrap().total_instructions(), 5);
+    }
+
+    #[test]
+    fn test_interpreter_profile() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        *interface.input_brainfuck.lock().unwrap() = "+++[>++<-]".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert!(interface.profile.lock().unwrap().is_none());
+
+        interface.profiling = true;
+        interface.optimize = false;
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        let profile = interface.profile.lock().unwrap().clone().unwrap();
+        assert_eq!(profile.loops()[0].iterations, 3);
+        assert_eq!(profile.max_cell(), 1);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let source = ",[.,]\n>+";
+        let run = |input: &[u8]| {
+            let program = Program::compile_with(source, &Coverage::optimizations()).unwrap();
+            let mut machine = Machine::from_program(program)
+                .with_eof_behavior(EofBehavior::Zero)
+                .with_profiling();
+            machine.push_input(input);
+            machine.close_input();
+            machine.run().unwrap();
+            machine.profile().unwrap().clone()
+        };
+        let mut coverage = Coverage::new(source);
+        coverage.add(&run(b""));
+        assert_eq!(coverage.is_covered(0), Some(true));
+        assert_eq!(coverage.is_covered(2), Some(false));
+        assert_eq!(coverage.is_covered(5), None);
+        assert_eq!(coverage.loops()[0].iterations, 0);
+
+        coverage.add(&run(b"AB"));
+        assert_eq!(coverage.runs(), 2);
+        assert_eq!(coverage.is_covered(2), Some(true));
+        assert_eq!(coverage.character_hits()[2], 2);
+        assert_eq!(coverage.loops()[0].entries, 2);
+        assert_eq!(coverage.loops()[0].iterations, 2);
+    }
+
+    #[test]
+    fn test_coverage_lcov() {
+        let mut coverage = Coverage::new("[+]\n+");
+        let program = Program::compile_with("[+]\n+", &Coverage::optimizations()).unwrap();
+        let mut machine = Machine::from_program(program).with_profiling();
+        machine.run().unwrap();
+        coverage.add(machine.profile().unwrap());
+        let lcov = coverage.to_lcov("a.bf");
+        assert_eq!(
+            lcov,
+            "TN:\nSF:a.bf\nBRDA:1,0,0,0\nBRF:1\nBRH:0\nDA:1,1\nDA:2,1\nLF:2\nLH:2\nend_of_record\n"
+        );
+
+        let other = "TN:\nSF:b.bf\nDA:1,3\nLF:1\nLH:1\nend_of_record\n";
+        let merged = coverage.merge_into_lcov(&format!("{other}{lcov}"), "a.bf");
+        assert!(merged.starts_with(other));
+        assert!(merged.contains("DA:1,2\n"));
+        assert!(merged.contains("BRDA:1,0,0,0\n"));
+        assert_eq!(merged.matches("SF:a.bf").count(), 1);
+    }
+
+    #[test]
+    fn test_interpreter_coverage() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.coverage_enabled = true;
+        interface.eof_behavior = EofBehavior::Zero;
+        *interface.input_brainfuck.lock().unwrap() = ",[.,]".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        let coverage = interface.current_coverage().unwrap();
+        assert_eq!(coverage.runs(), 1);
+        assert_eq!(coverage.is_covered(2), Some(false));
+
+        *interface.input_text.lock().unwrap() = "A".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        let coverage = interface.current_coverage().unwrap();
+        assert_eq!(coverage.runs(), 2);
+        assert_eq!(coverage.is_covered(2), Some(true));
+
+        // Changing the code starts over
+        *interface.input_brainfuck.lock().unwrap() = ",[.,]+".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(interface.current_coverage().unwrap().runs(), 1);
+    }
+
+    /// Runs `source` on a [`Machine`] with `options` and all of `input`, returning the
+    /// output and whether it finished without an error.
+    fn run_machine(source: &str, options: &TranspileOptions, input: &[u8]) -> (Vec<u8>, bool) {
+        let program = Program::compile_with(source, &Optimizations::all()).unwrap();
+        let mut machine = Machine::from_program(program)
+            .with_tape_size(options.tape_size)
+            .with_tape_mode(options.tape_mode)
+            .with_cell_width(options.cell_width)
+            .with_eof_behavior(options.eof_behavior);
+        machine.push_input(input);
+        machine.close_input();
+        let result = machine.run();
+        (machine.take_output(), result == Ok(Status::Halted))
+    }
+
+    /// Programs and tape settings the transpilers are checked against the interpreter with.
+    fn transpile_cases() -> [(&'static str, TranspileOptions, &'static [u8]); 8] {
+        [
+            (include_str!("../hello.bf"), TranspileOptions::default(), b""),
+            (
+                "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+                TranspileOptions::default(),
+                b"",
+            ),
+            (
+                ",[.,]",
+                TranspileOptions {
+                    eof_behavior: EofBehavior::Zero,
+                    ..TranspileOptions::default()
+                },
+                b"echo \xff",
+            ),
+            (
+                ",.,.",
+                TranspileOptions {
+                    eof_behavior: EofBehavior::MinusOne,
+                    cell_width: CellWidth::Bits16,
+                    ..TranspileOptions::default()
+                },
+                b"",
+            ),
+            (
+                "++++++++[>++++++++<-]>[>++++<-]>[>++++<-]>[<<<+>>>-]<<<.-.",
+                TranspileOptions {
+                    cell_width: CellWidth::Bits32,
+                    tape_mode: TapeMode::Bidirectional,
+                    tape_size: 1,
+                    ..TranspileOptions::default()
+                },
+                b"",
+            ),
+            (
+                "<+++++[>+++++++++++++<-]>.<.",
+                TranspileOptions {
+                    tape_mode: TapeMode::Circular,
+                    tape_size: 3,
+                    ..TranspileOptions::default()
+                },
+                b"",
+            ),
+            ("+++++++++[>>>>>>>+<<<<<<<-]>>>>>>>.<<<<<<<<.", TranspileOptions::default(), b""),
+            (
+                ",.,.",
+                TranspileOptions {
+                    eof_behavior: EofBehavior::Error,
+                    ..TranspileOptions::default()
+                },
+                b"A",
+            ),
+        ]
+    }
+
+    /// Writes every case translated by `generate` as `name/<case>.<extension>`, builds it
+    /// with `build(source, executable)` and checks it prints what the interpreter prints.
+    /// Fails if the compiler cannot be started, unless `BF_SKIP_MISSING_COMPILERS` is set.
+    fn check_transpiled<T: AsRef<[u8]>>(
+        name: &str,
+        extension: &str,
+        generate: fn(&Program, &TranspileOptions) -> T,
+        build: impl Fn(&std::path::Path, &std::path::Path) -> std::io::Result<std::process::ExitStatus>,
+    ) {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let dir = std::env::temp_dir().join(format!("bf_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, (source, options, input)) in transpile_cases().iter().enumerate() {
+            let program = Program::compile_with(source, &Optimizations::all()).unwrap();
+            let source_path = dir.join(format!("{i}.{extension}"));
+            let exe_path = dir.join(format!("{i}"));
+            std::fs::write(&source_path, generate(&program, options)).unwrap();
+            let status = match build(&source_path, &exe_path) {
+                Ok(status) => status,
+                Err(_) if std::env::var_os("BF_SKIP_MISSING_COMPILERS").is_some() => {
+                    eprintln!("No compiler for {name}, skipping");
+                    return;
+                }
+                Err(e) => panic!(
+                    "No compiler for {name}: {e}. Set BF_SKIP_MISSING_COMPILERS to skip this check"
+                ),
+            };
+            assert!(status.success(), "case {i} did not compile");
+
+            let mut child = Command::new(&exe_path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let result = child.wait_with_output().unwrap();
+            let (expected, finished) = run_machine(source, options, input);
+            assert_eq!(result.stdout, expected, "case {i}");
+            assert_eq!(result.status.success(), finished, "case {i}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_transpile_c() {
+        check_transpiled("c", "c", to_c, |source, exe| {
+            std::process::Command::new("cc")
+                .arg("-std=c99")
+                .arg("-O1")
+                .arg(source)
+                .arg("-o")
+                .arg(exe)
+                .status()
+        });
+    }
+
+    #[test]
+    fn test_transpile_rust() {
+        check_transpiled("rust", "rs", to_rust, |source, exe| {
+            std::process::Command::new("rustc")
+                .arg("--edition=2021")
+                .arg("-Dwarnings")
+                .arg(source)
+                .arg("-o")
+                .arg(exe)
+                .status()
+        });
+        // The generated code must also be clean under clippy, which compiles it like rustc
+        check_transpiled("rust_clippy", "rs", to_rust, |source, exe| {
+            std::process::Command::new("clippy-driver")
+                .arg("--edition=2021")
+                .arg("-Dwarnings")
+                .arg("-Dclippy::all")
+                .arg(source)
+                .arg("-o")
+                .arg(exe)
+                .status()
+        });
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn test_transpile_elf() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::process::ExitStatusExt;
+
+        check_transpiled("elf", "elf", to_elf, |source, exe| {
+            std::fs::copy(source, exe)?;
+            std::fs::set_permissions(exe, std::fs::Permissions::from_mode(0o755))?;
+            Ok(std::process::ExitStatus::from_raw(0))
+        });
+
+        // Errors are reported like the interpreter reports them
+        let dir = std::env::temp_dir().join(format!("bf_elf_errors_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("left");
+        let program = Program::compile_with("+.<", &Optimizations::all()).unwrap();
+        std::fs::write(&exe, to_elf(&program, &TranspileOptions::default())).unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let result = std::process::Command::new(&exe).output().unwrap();
+        assert_eq!(result.stdout, b"\x01");
+        assert_eq!(result.stderr, b"Tried to go out of bounds at character 2\n");
+        assert_eq!(result.status.code(), Some(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Calls `run` of a module from [`to_wasm`] or [`to_wat`] `calls` times with `input`,
+    /// returning the output and the exported `error` and `position` if it trapped.
+    fn run_wasm(wasm: &[u8], input: &[u8], calls: usize) -> (Vec<u8>, Option<(i32, i32)>) {
+        use wasmi::{Caller, Engine, Linker, Module, Store};
+
+        struct Host {
+            input: Vec<u8>,
+            output: Vec<u8>,
+        }
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).unwrap();
+        let host = Host {
+            input: input.iter().rev().copied().collect(),
+            output: Vec::new(),
+        };
+        let mut store = Store::new(&engine, host);
+        let mut linker = <Linker<Host>>::new(&engine);
+        linker
+            .func_wrap("env", "read_byte", |mut caller: Caller<'_, Host>| {
+                caller.data_mut().input.pop().map_or(-1, i32::from)
+            })
+            .unwrap();
+        linker
+            .func_wrap("env", "write_byte", |mut caller: Caller<'_, Host>, byte: i32| {
+                caller.data_mut().output.push(byte as u8);
+            })
+            .unwrap();
+        let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+        let run = instance.get_typed_func::<(), ()>(&store, "run").unwrap();
+        let error = (0..calls).find_map(|_| run.call(&mut store, ()).err()).map(|_| {
+            let global = |name| instance.get_global(&store, name).unwrap().get(&store).i32().unwrap();
+            (global("error"), global("position"))
+        });
+        (std::mem::take(&mut store.data_mut().output), error)
+    }
+
+    #[test]
+    fn test_transpile_wasm() {
+        for (i, (source, options, input)) in transpile_cases().iter().enumerate() {
+            let program = Program::compile_with(source, &Optimizations::all()).unwrap();
+            let (expected, finished) = run_machine(source, options, input);
+            let wat = wat::parse_str(to_wat(&program, options)).unwrap();
+            for wasm in [to_wasm(&program, options), wat] {
+                let (output, error) = run_wasm(&wasm, input, 1);
+                assert_eq!(output, expected, "case {i}");
+                assert_eq!(error.is_none(), finished, "case {i}");
+            }
+        }
+
+        // The tape grows past the first page of memory on both ends
+        let far = 20_000;
+        for (source, tape_mode) in [
+            (format!("+[-{}+{}]{}.", ">".repeat(far), "<".repeat(far), ">".repeat(far)), TapeMode::Standard),
+            (format!("+[-{}+{}]{}.", "<".repeat(far), ">".repeat(far), "<".repeat(far)), TapeMode::Bidirectional),
+        ] {
+            let options = TranspileOptions {
+                tape_mode,
+                ..TranspileOptions::default()
+            };
+            let program = Program::compile_with(&source, &Optimizations::all()).unwrap();
+            assert_eq!(run_wasm(&to_wasm(&program, &options), b"", 1), (vec![1], None), "{tape_mode}");
+        }
+
+        // Errors say what went wrong and where
+        let program = Program::compile_with("+.<", &Optimizations::all()).unwrap();
+        let wasm = to_wasm(&program, &TranspileOptions::default());
+        assert_eq!(run_wasm(&wasm, b"", 1), (vec![1], Some((1, 2))));
+        let program = Program::compile_with(",,", &Optimizations::all()).unwrap();
+        let options = TranspileOptions {
+            eof_behavior: EofBehavior::Error,
+            ..TranspileOptions::default()
+        };
+        assert_eq!(run_wasm(&to_wasm(&program, &options), b"A", 1), (vec![], Some((2, 1))));
+
+        // Every call starts from a zeroed tape at the first cell
+        let program = Program::compile_with(">+.>+.", &Optimizations::all()).unwrap();
+        let wasm = to_wasm(&program, &TranspileOptions::default());
+        assert_eq!(run_wasm(&wasm, b"", 2), (vec![1, 1, 1, 1], None));
+    }
+
+    #[test]
+    fn test_jit() {
+        // Without the `jit` feature this compares the interpreter with itself
+        for (i, (source, options, input)) in transpile_cases().iter().enumerate() {
+            let program = Program::compile_with(source, &Optimizations::all()).unwrap();
+            let new_machine = || {
+                let mut machine = Machine::from_program(program.clone())
+                    .with_tape_size(options.tape_size)
+                    .with_tape_mode(options.tape_mode)
+                    .with_cell_width(options.cell_width)
+                    .with_eof_behavior(options.eof_behavior);
+                machine.push_input(input);
+                machine.close_input();
+                machine
+            };
+            let mut interpreted = new_machine();
+            let mut compiled = new_machine().with_jit();
+            // Small budgets stop the compiled code in the middle of loops
+            for budget in [1, 7, 100, 1_000_000] {
+                let expected = interpreted.run_for(budget);
+                assert_eq!(compiled.run_for(budget), expected, "case {i}");
+                assert_eq!(compiled.instructions_executed(), interpreted.instructions_executed(), "case {i}");
+                assert_eq!(compiled.data_pointer(), interpreted.data_pointer(), "case {i}");
+                assert_eq!(compiled.tape(), interpreted.tape(), "case {i}");
+                assert_eq!(compiled.output(), interpreted.output(), "case {i}");
+            }
+            assert_eq!(compiled.run(), interpreted.run(), "case {i}");
+            assert_eq!(compiled.take_output(), interpreted.take_output(), "case {i}");
+        }
+    }
+
+    #[test]
+    fn test_jit_cell_widths() {
+        let source = "-->+++++[>+++++++++++<-]>[>+++>++++++<<-]>[>>++++<<-]>>>>>>+[<]<<[->>+<<]>.<<<<<<<-[>]+";
+        for cell_width in CellWidth::ALL {
+            for tape_mode in TapeMode::ALL {
+                let new_machine = || {
+                    Machine::new(source)
+                        .unwrap()
+                        .with_tape_size(4)
+                        .with_tape_mode(tape_mode)
+                        .with_cell_width(cell_width)
+                };
+                let mut interpreted = new_machine();
+                let mut compiled = new_machine().with_jit();
+                let expected = interpreted.run();
+                assert_eq!(compiled.run(), expected, "{cell_width} {tape_mode}");
+                assert_eq!(compiled.instructions_executed(), interpreted.instructions_executed());
+                assert_eq!(compiled.tape(), interpreted.tape(), "{cell_width} {tape_mode}");
+                assert_eq!(compiled.output(), interpreted.output());
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpreter_jit() {
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.jit = true;
+        interface.eof_behavior = EofBehavior::Zero;
+        *interface.input_brainfuck.lock().unwrap() = "-[>-[>-[-]<-]<-]>>+[<+>-],[.,]".to_string();
+        *interface.input_text.lock().unwrap() = "JIT".to_string();
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "JIT");
+        assert!(!interface.can_step_back());
+    }
+
+    #[test]
+    fn test_text_to_brainfuck() {
+        let texts = ["", "A", "Hello, World!\n", "aaaa", "Grüße, 世界 🦀", "The quick brown fox jumps over the lazy dog."];
+        for cell_width in CellWidth::ALL {
+            for text in texts {
+                let program = text_to_brainfuck(text, cell_width);
+                let mut machine = Machine::new(&program).unwrap().with_cell_width(cell_width);
+                assert_eq!(machine.run(), Ok(Status::Halted), "{text:?} {cell_width}");
+                let expected = match cell_width {
+                    CellWidth::Bits16 => text.replace('🦀', "\u{FFFD}"),
+                    _ => text.to_string(),
+                };
+                assert_eq!(machine.output(), expected.as_bytes(), "{text:?} {cell_width}");
+            }
+        }
+
+        // Much shorter than adding up each character from zero
+        let program = text_to_brainfuck("Hello, World!\n", CellWidth::Bits8);
+        assert!(program.contains('['), "{program}");
+        assert!(program.len() < 140, "{} characters: {program}", program.len());
+    }
+
+    #[derive(Default)]
+    struct MemoryStorage(std::collections::HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_string(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    #[test]
+    fn test_persistence() {
+        use eframe::App;
+        let mut storage = MemoryStorage::default();
+        let mut app = BrainfuckInterpreterApp::default();
+        app.tabs[0].breakpoints.insert(String::new(), [3].into());
+        app.tabs[0].memory_size = 12;
+        let interface = &app.tabs[0];
+        *interface.input_brainfuck.lock().unwrap() = ",[.,]".to_string();
+        *interface.input_text.lock().unwrap() = "abc".to_string();
+        *interface.delay.lock().unwrap() = 7;
+        *interface.output.lock().unwrap() = "not saved".to_string();
+        app.new_tab();
+        *app.tabs[1].input_brainfuck.lock().unwrap() = "+.".to_string();
+        app.save(&mut storage);
+        let restored = BrainfuckInterpreterApp::restore(Some(&storage));
+        assert_eq!(restored.tabs.len(), 2);
+        assert_eq!(restored.active, 1);
+        let interface = &restored.tabs[0];
+        assert_eq!(*interface.input_brainfuck.lock().unwrap(), ",[.,]");
+        assert_eq!(*interface.input_text.lock().unwrap(), "abc");
+        assert_eq!(*interface.delay.lock().unwrap(), 7);
+        assert_eq!(*interface.data.lock().unwrap(), vec![0; 12]);
+        assert_eq!(*interface.output.lock().unwrap(), "");
+        assert_eq!(interface.current_breakpoints(), [3].into());
+        assert_eq!(*restored.tabs[1].input_brainfuck.lock().unwrap(), "+.");
+    }
+
+    #[test]
+    fn test_tabs() {
+        let mut app = BrainfuckInterpreterApp::default();
+        app.new_tab();
+        // A run that never ends in the first tab does not hold up the second
+        for (tab, code) in app.tabs.iter_mut().zip(["+[]", "+++."]) {
+            tab.delay = Arc::new(Mutex::new(0u64));
+            *tab.input_brainfuck.lock().unwrap() = code.to_string();
+            tab.start_interpreter();
+        }
+        app.tabs[1].timer_thread_handle.take().unwrap().join().unwrap();
+        assert_eq!(*app.tabs[1].output.lock().unwrap(), "\u{03}");
+        assert!(*app.tabs[0].timer_running.lock().unwrap());
+
+        // Unsaved changes are asked about before the tab closes
+        app.close_tab(0);
+        app.remove_closed_tabs();
+        assert_eq!(app.tabs.len(), 2);
+        assert!(*app.tabs[0].timer_running.lock().unwrap());
+
+        // Closing a tab stops its run
+        let path = std::env::temp_dir().join(format!("bf_test_tabs_{}.bf", std::process::id()));
+        assert!(app.tabs[0].save_to(path.to_str().unwrap().to_string()));
+        std::fs::remove_file(&path).unwrap();
+        let running = Arc::clone(&app.tabs[0].timer_running);
+        let handle = app.tabs[0].timer_thread_handle.take().unwrap();
+        app.close_tab(0);
+        app.remove_closed_tabs();
+        handle.join().unwrap();
+        assert!(!*running.lock().unwrap());
+        assert_eq!(app.tabs.len(), 1);
+        assert_eq!(app.active, 0);
+        assert_eq!(*app.tabs[0].input_brainfuck.lock().unwrap(), "+++.");
+
+        // The last tab is replaced by an empty one
+        app.tabs[0].input_brainfuck.lock().unwrap().clear();
+        app.close_tab(0);
+        app.remove_closed_tabs();
+        assert_eq!(app.tabs.len(), 1);
+    }
+
+    #[test]
+    fn test_save() {
+        let path = std::env::temp_dir().join(format!("bf_test_save_{}.bf", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut interface = BrainfuckInterpreterInterface::default();
+        assert!(!interface.is_dirty());
+        assert_eq!(interface.window_title(), "Untitled - Brainfuck Interpreter");
+
+        *interface.input_brainfuck.lock().unwrap() = "+++ add three\n.".to_string();
+        interface.breakpoints.insert(String::new(), [1].into());
+        assert!(interface.is_dirty());
+        assert_eq!(interface.window_title(), "*Untitled - Brainfuck Interpreter");
+
+        assert!(interface.save_to(path.clone()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "+++ add three\n.");
+        assert!(!interface.is_dirty());
+        assert!(interface.window_title().starts_with("bf_test_save_"));
+        // The breakpoints of the unsaved code move to the file
+        assert_eq!(interface.current_breakpoints(), [1].into());
+        assert!(!interface.breakpoints.contains_key(""));
+
+        interface.input_brainfuck.lock().unwrap().push('.');
+        assert!(interface.window_title().starts_with("*bf_test_save_"));
+        std::fs::remove_file(&path).unwrap();
+
+        // A failed save keeps the changes marked and warns
+        let missing = std::env::temp_dir().join("bf_missing_dir").join("a.bf");
+        assert!(!interface.save_to(missing.to_str().unwrap().to_string()));
+        assert!(interface.is_dirty());
+        assert!(*interface.warn.lock().unwrap());
+    }
+
+    #[test]
+    fn test_load_keeps_comments() {
+        let path = std::env::temp_dir().join(format!("bf_test_load_{}.bf", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let code = "Print A\n++++++++[>++++++++<-]>+. print it\n";
+        std::fs::write(&path, code).unwrap();
+        let mut interface = BrainfuckInterpreterInterface::default();
+        interface.load(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(*interface.input_brainfuck.lock().unwrap(), code);
+        assert!(!interface.is_dirty());
+
+        // Comments are skipped when it runs
+        interface.delay = Arc::new(Mutex::new(0u64));
+        interface.start_interpreter();
+        if let Some(handle) = interface.timer_thread_handle.take() {
+            handle.join().unwrap();
+        }
+        assert_eq!(*interface.output.lock().unwrap(), "A");
+
+        interface.load(path);
+        assert!(*interface.warn.lock().unwrap());
+        assert_eq!(*interface.input_brainfuck.lock().unwrap(), code);
+    }
+
+    #[test]
+    fn test_bracket_partners() {
+        use crate::app::bracket_partners;
+        assert_eq!(
+            bracket_partners("+[a[-]>]"),
+            vec![None, Some(7), None, Some(5), None, Some(3), None, Some(1)]
+        );
+        // The innermost brackets pair up, the extra ones are left without a partner
+        assert_eq!(bracket_partners("[[]"), vec![None, Some(2), Some(1)]);
+        assert_eq!(bracket_partners("]["), vec![None, None]);
+        // Indices count characters, not bytes
+        assert_eq!(bracket_partners("[é]"), vec![Some(2), None, Some(0)]);
+    }
 }
@@ -0,0 +1,243 @@
+use super::TranspileOptions;
+use crate::bytecode::{Op, Program};
+use crate::machine::{CellWidth, EofBehavior, TapeMode};
+use std::fmt::Write;
+
+/// Translates `program` into a standalone C99 file.
+///
+/// Runtime errors are printed to stderr like `Tried to go out of bounds at character 3`
+/// and exit with status 1, after the output so far has been written.
+pub fn to_c(program: &Program, options: &TranspileOptions) -> String {
+    let mut c = String::new();
+    let cell_type = match options.cell_width {
+        CellWidth::Bits8 => "uint8_t",
+        CellWidth::Bits16 => "uint16_t",
+        CellWidth::Bits32 => "uint32_t",
+        CellWidth::Bits64 => "uint64_t",
+    };
+    let _ = writeln!(c, "/* Generated from Brainfuck. */");
+    let _ = writeln!(c, "#include <stddef.h>");
+    let _ = writeln!(c, "#include <stdint.h>");
+    let _ = writeln!(c, "#include <stdio.h>");
+    let _ = writeln!(c, "#include <stdlib.h>");
+    let _ = writeln!(c, "#include <string.h>");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "typedef {cell_type} cell;");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "static cell *tape;");
+    let _ = writeln!(c, "static size_t len;");
+    let instructions = program.instructions();
+    let dumps = instructions
+        .iter()
+        .any(|instruction| instruction.op == Op::Dump);
+    if dumps || options.tape_mode == TapeMode::Bidirectional {
+        let _ = writeln!(c, "/* Index in tape of logical cell 0 */");
+        let _ = writeln!(c, "static size_t origin;");
+    }
+    let _ = writeln!(c, "static size_t p;");
+    c.push_str(RUNTIME);
+    if dumps {
+        c.push_str(DUMP);
+    }
+    c.push_str(at_function(options.tape_mode));
+    c.push_str(output_function(options.cell_width));
+    c.push_str(&input_function(options.eof_behavior));
+
+    let _ = writeln!(c, "int main(void) {{");
+    let _ = writeln!(c, "    grow({});", options.tape_size.max(1));
+    let mut depth = 1;
+    for instruction in instructions {
+        let position = instruction.position;
+        let line = match instruction.op {
+            Op::Add(n) => format!("tape[p] += {};", literal(n as u64, options.cell_width)),
+            Op::Move(n) => format!("p = at({n}, {position});"),
+            Op::Output => "output(tape[p]);".to_string(),
+            Op::Input => format!("input({position});"),
+            Op::JumpIfZero(_) => "while (tape[p]) {".to_string(),
+            Op::JumpIfNotZero(_) => {
+                depth -= 1;
+                "}".to_string()
+            }
+            Op::SetZero => "tape[p] = 0;".to_string(),
+            Op::MulAdd { offset, factor } => format!(
+                "if (tape[p]) {{ size_t t = at({offset}, {position}); tape[t] += (cell)(tape[p] * {}); }}",
+                literal(factor as u64, options.cell_width)
+            ),
+            Op::Scan(step) => format!("while (tape[p]) p = at({step}, {position});"),
+            Op::Dump => "dump();".to_string(),
+        };
+        let _ = writeln!(c, "{:indent$}{line}", "", indent = depth * 4);
+        if matches!(instruction.op, Op::JumpIfZero(_)) {
+            depth += 1;
+        }
+    }
+    let _ = writeln!(c, "    return 0;");
+    let _ = writeln!(c, "}}");
+    c
+}
+
+/// `value` truncated to the cell width, as an unsigned C literal.
+fn literal(value: u64, cell_width: CellWidth) -> String {
+    let value = value & cell_width.max_value();
+    if cell_width == CellWidth::Bits64 {
+        format!("{value}ull")
+    } else {
+        format!("{value}u")
+    }
+}
+
+/// Helpers every program needs: failing and growing the tape.
+const RUNTIME: &str = r##"
+static inline void fail(const char *message, size_t position) {
+    fflush(stdout);
+    fprintf(stderr, "%s at character %lu\n", message, (unsigned long)position);
+    exit(1);
+}
+
+/* Grows the tape to new_len cells, the new ones on the right are zero. */
+static inline void grow(size_t new_len) {
+    tape = realloc(tape, new_len * sizeof(cell));
+    if (!tape) {
+        fputs("Out of memory\n", stderr);
+        exit(1);
+    }
+    memset(tape + len, 0, (new_len - len) * sizeof(cell));
+    len = new_len;
+}
+"##;
+
+/// `dump()`, only emitted for programs compiled with `#` dumps.
+const DUMP: &str = r##"
+/* Prints the used part of the tape to stderr, like `bf --dump`. */
+static inline void dump(void) {
+    size_t used = p + 1;
+    size_t i;
+    fflush(stdout);
+    for (i = len; i > used; i--) {
+        if (tape[i - 1]) {
+            used = i;
+            break;
+        }
+    }
+    fprintf(stderr, "#: cell %ld:", (long)p - (long)origin);
+    for (i = 0; i < used; i++) {
+        fprintf(stderr, i == p ? " [%llu]" : " %llu", (unsigned long long)tape[i]);
+    }
+    fputc('\n', stderr);
+}
+"##;
+
+/// `at(offset, position)`: the index of the cell `offset` away from the current one,
+/// growing the tape if the mode allows it.
+fn at_function(tape_mode: TapeMode) -> &'static str {
+    match tape_mode {
+        TapeMode::Standard => {
+            r##"
+static inline size_t at(ptrdiff_t offset, size_t position) {
+    size_t index;
+    if (offset < 0 && (size_t)-offset > p) {
+        fail("Tried to go out of bounds", position);
+    }
+    index = p + offset;
+    if (index >= len) {
+        grow(index + 1 > len * 2 ? index + 1 : len * 2);
+    }
+    return index;
+}
+"##
+        }
+        TapeMode::Bidirectional => {
+            r##"
+static inline size_t at(ptrdiff_t offset, size_t position) {
+    size_t index;
+    (void)position;
+    if (offset < 0 && (size_t)-offset > p) {
+        /* Grow the left side at least as much as the tape already is */
+        size_t missing = (size_t)-offset - p;
+        size_t added = missing > len ? missing : len;
+        size_t old_len = len;
+        grow(len + added);
+        memmove(tape + added, tape, old_len * sizeof(cell));
+        memset(tape, 0, added * sizeof(cell));
+        origin += added;
+        p += added;
+    }
+    index = p + offset;
+    if (index >= len) {
+        grow(index + 1 > len * 2 ? index + 1 : len * 2);
+    }
+    return index;
+}
+"##
+        }
+        TapeMode::Circular => {
+            r##"
+static inline size_t at(ptrdiff_t offset, size_t position) {
+    ptrdiff_t n = (ptrdiff_t)len;
+    (void)position;
+    return (size_t)((((ptrdiff_t)p + offset % n) % n + n) % n);
+}
+"##
+        }
+    }
+}
+
+/// `output(value)`: a raw byte for 8-bit cells, UTF-8 for wider ones.
+fn output_function(cell_width: CellWidth) -> &'static str {
+    if cell_width == CellWidth::Bits8 {
+        return r##"
+static inline void output(cell value) {
+    putchar(value);
+}
+"##;
+    }
+    r##"
+static inline void output(cell cell_value) {
+    unsigned long long value = cell_value;
+    if (value > 0x10FFFF || (value >= 0xD800 && value <= 0xDFFF)) {
+        value = 0xFFFD;
+    }
+    if (value < 0x80) {
+        putchar((int)value);
+    } else if (value < 0x800) {
+        putchar((int)(0xC0 | (value >> 6)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    } else if (value < 0x10000) {
+        putchar((int)(0xE0 | (value >> 12)));
+        putchar((int)(0x80 | ((value >> 6) & 0x3F)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    } else {
+        putchar((int)(0xF0 | (value >> 18)));
+        putchar((int)(0x80 | ((value >> 12) & 0x3F)));
+        putchar((int)(0x80 | ((value >> 6) & 0x3F)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    }
+}
+"##
+}
+
+/// `input(position)`: reads one byte of stdin into the current cell.
+fn input_function(eof_behavior: EofBehavior) -> String {
+    let at_eof = match eof_behavior {
+        EofBehavior::Unchanged => return INPUT.replace("EOF_CASE", ""),
+        EofBehavior::Zero => "tape[p] = 0;",
+        EofBehavior::MinusOne => "tape[p] = (cell)-1;",
+        EofBehavior::Block | EofBehavior::Error => {
+            "fail(\"Tried to read past the end of input\", position);"
+        }
+    };
+    INPUT.replace("EOF_CASE", &format!(" else {{\n        {at_eof}\n    }}"))
+}
+
+const INPUT: &str = r##"
+static inline void input(size_t position) {
+    int c;
+    (void)position;
+    fflush(stdout);
+    c = getchar();
+    if (c != EOF) {
+        tape[p] = (cell)c;
+    }EOF_CASE
+}
+
+"##;
@@ -0,0 +1,436 @@
+use super::TranspileOptions;
+use crate::bytecode::{Op, Program};
+use crate::machine::{CellWidth, EofBehavior, MachineError, TapeMode};
+use crate::x86::{
+    Assembler, Label, ADD, AND, CMP, JA, JAE, JB, JLE, JNZ, JS, JZ, OR, R10, R12, R13, R8, R9, RAX,
+    RBX, RCX, RDI, RDX, RSI, SUB,
+};
+
+/// Where the file is loaded, the usual address for non-PIE executables.
+const BASE_ADDRESS: u64 = 0x40_0000;
+/// Size of the ELF header plus the one program header in front of the code.
+const HEADERS_SIZE: u64 = 64 + 56;
+/// Bytes of output collected before a `write`.
+const BUFFER_SIZE: i32 = 4096;
+/// Least address space reserved for a growing tape. It is only backed by memory once used.
+const MIN_RESERVED_CELLS: u64 = 1 << 28;
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_MMAP: u64 = 9;
+const SYS_EXIT_GROUP: u64 = 231;
+
+const MOV_LOAD: u8 = 0x8B;
+const LEA: u8 = 0x8D;
+
+/// Translates `program` into a static Linux x86-64 executable that needs no libc.
+///
+/// Runtime errors are printed to stderr like `Tried to go out of bounds at character 3`
+/// and exit with status 1, after the output so far has been written. Unless it is
+/// circular, the tape cannot grow like an interpreter's does: it reserves `tape_size`
+/// cells but at least 2^28, and a program that goes past them fails with
+/// `Out of memory`. `#` dumps are ignored.
+pub fn to_elf(program: &Program, options: &TranspileOptions) -> Vec<u8> {
+    let mut elf = Elf::new(options);
+    let instructions = program.instructions();
+    let entries: Vec<Label> = (0..=instructions.len())
+        .map(|_| elf.asm.new_label())
+        .collect();
+
+    elf.start();
+    for (index, instruction) in instructions.iter().enumerate() {
+        elf.asm.bind(entries[index]);
+        let position = instruction.position;
+        let asm = &mut elf.asm;
+        match instruction.op {
+            Op::Add(n) => {
+                asm.load_cell(RAX, R10);
+                asm.mov_imm(RCX, n as u64);
+                asm.add(RAX, RCX);
+                asm.mask(options.cell_width);
+                asm.store_cell(R10, RAX);
+            }
+            Op::Move(n) => {
+                elf.move_pointer(RAX, n, position);
+                elf.asm.mov(R10, RAX);
+            }
+            Op::Output => {
+                asm.load_cell(RAX, R10);
+                asm.call(elf.output);
+            }
+            Op::Input => elf.input(position),
+            Op::JumpIfZero(target) | Op::JumpIfNotZero(target) => {
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                let condition = if matches!(instruction.op, Op::JumpIfZero(_)) {
+                    JZ
+                } else {
+                    JNZ
+                };
+                asm.jump_if(condition, entries[target]);
+            }
+            Op::SetZero => {
+                asm.mov_imm(RAX, 0);
+                asm.store_cell(R10, RAX);
+            }
+            Op::MulAdd { offset, factor } => {
+                let done = asm.new_label();
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                asm.jump_if(JZ, done);
+                elf.move_pointer(RCX, offset, position);
+                let asm = &mut elf.asm;
+                asm.mov_imm(RDX, factor as u64);
+                asm.imul(RAX, RDX);
+                asm.cell_op(0x03, RAX, RCX); // add rax, [r8 + rcx * 8]
+                asm.mask(options.cell_width);
+                asm.store_cell(RCX, RAX);
+                asm.bind(done);
+            }
+            Op::Scan(step) => {
+                let scan = asm.new_label();
+                let done = asm.new_label();
+                asm.bind(scan);
+                asm.load_cell(RAX, R10);
+                asm.test(RAX);
+                asm.jump_if(JZ, done);
+                elf.move_pointer(RAX, step, position);
+                elf.asm.mov(R10, RAX);
+                elf.asm.jump(scan);
+                elf.asm.bind(done);
+            }
+            Op::Dump => {}
+        }
+    }
+    elf.asm.bind(entries[instructions.len()]);
+    elf.asm.call(elf.flush);
+    exit(&mut elf.asm, 0);
+
+    elf.finish()
+}
+
+/// The program being generated, with the labels of its helpers.
+///
+/// While it runs, `r8`, `r9` and `r10` hold the tape as usual, `r13` the output
+/// buffer (followed by one byte for input) and `r12` the number of bytes in it.
+struct Elf {
+    asm: Assembler,
+    options: TranspileOptions,
+    /// Writes out the buffered output.
+    flush: Label,
+    /// Buffers the byte in `al`.
+    put_byte: Label,
+    /// Buffers the cell value in `rax`, as a byte or as UTF-8 depending on the cell width.
+    output: Label,
+    /// Reads one byte of stdin into `rax`, or -1 at the end of it.
+    read_byte: Label,
+    /// Prints the message at `rbx` to stderr and exits with 1.
+    fail: Label,
+    /// Error messages, each with the stub that fails with it and its text.
+    messages: Vec<(String, Label, Label)>,
+}
+
+impl Elf {
+    fn new(options: &TranspileOptions) -> Self {
+        let mut asm = Assembler::default();
+        Self {
+            flush: asm.new_label(),
+            put_byte: asm.new_label(),
+            output: asm.new_label(),
+            read_byte: asm.new_label(),
+            fail: asm.new_label(),
+            asm,
+            options: *options,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Maps the buffer and the tape and sets up the registers.
+    fn start(&mut self) {
+        let reserved = (self.options.tape_size as u64).max(MIN_RESERVED_CELLS);
+        let (cells, data_pointer) = match self.options.tape_mode {
+            TapeMode::Standard => (reserved, 0),
+            TapeMode::Bidirectional => (reserved, reserved / 2),
+            TapeMode::Circular => (self.options.tape_size.max(1) as u64, 0),
+        };
+        let tape_offset = 2 * BUFFER_SIZE as u64;
+        let out_of_memory = self.error("Out of memory".to_string());
+        let asm = &mut self.asm;
+        // mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0)
+        asm.mov_imm(RAX, SYS_MMAP);
+        asm.mov_imm(RDI, 0);
+        asm.mov_imm(RSI, tape_offset + cells * 8);
+        asm.mov_imm(RDX, 0x1 | 0x2);
+        asm.mov_imm(R10, 0x02 | 0x20 | 0x4000);
+        asm.mov_imm(R8, u64::MAX);
+        asm.mov_imm(R9, 0);
+        asm.syscall();
+        // Errors come back as -4095..-1
+        asm.alu_imm(CMP, RAX, -4096);
+        asm.jump_if(JA, out_of_memory);
+        asm.mov(R13, RAX);
+        asm.mov(R8, RAX);
+        asm.alu_imm(ADD, R8, tape_offset as i32);
+        asm.mov_imm(R9, cells);
+        asm.mov_imm(R10, data_pointer);
+        asm.mov_imm(R12, 0);
+    }
+
+    /// `dst = r10 + offset` on the tape, failing at `position` if that is outside of it.
+    fn move_pointer(&mut self, dst: u8, offset: isize, position: usize) {
+        if self.options.tape_mode == TapeMode::Circular {
+            let len = self.options.tape_size.max(1) as isize;
+            let asm = &mut self.asm;
+            let inside = asm.new_label();
+            asm.mov(dst, R10);
+            asm.mov_imm(RDX, offset.rem_euclid(len) as u64);
+            asm.add(dst, RDX);
+            asm.cmp(dst, R9);
+            asm.jump_if(JB, inside);
+            asm.reg_op(0x29, dst, R9); // sub dst, r9
+            asm.bind(inside);
+            return;
+        }
+        // Only a standard tape has a left end, the rest of the reservation is the limit
+        let outside = if self.options.tape_mode == TapeMode::Standard && offset < 0 {
+            let e = MachineError::OutOfBounds { position };
+            self.error(format!("{e} at character {position}"))
+        } else {
+            self.error("Out of memory".to_string())
+        };
+        self.asm.offset_pointer(dst, offset, outside);
+    }
+
+    /// `,` at `position`.
+    fn input(&mut self, position: usize) {
+        let max_value = self.options.cell_width.max_value();
+        let end_of_input = match self.options.eof_behavior {
+            EofBehavior::Block | EofBehavior::Error => {
+                let e = MachineError::EndOfInput { position };
+                Some(self.error(format!("{e} at character {position}")))
+            }
+            _ => None,
+        };
+        let asm = &mut self.asm;
+        let at_end = asm.new_label();
+        let done = asm.new_label();
+        asm.call(self.read_byte);
+        asm.test(RAX);
+        asm.jump_if(JS, at_end);
+        asm.store_cell(R10, RAX);
+        asm.jump(done);
+        asm.bind(at_end);
+        match self.options.eof_behavior {
+            EofBehavior::Unchanged => {}
+            EofBehavior::Zero => {
+                asm.mov_imm(RAX, 0);
+                asm.store_cell(R10, RAX);
+            }
+            EofBehavior::MinusOne => {
+                asm.mov_imm(RAX, max_value);
+                asm.store_cell(R10, RAX);
+            }
+            EofBehavior::Block | EofBehavior::Error => {
+                asm.jump(end_of_input.expect("failing EOF behaviors have a message"));
+            }
+        }
+        asm.bind(done);
+    }
+
+    /// A stub that fails with `message`, shared by every place with the same message.
+    fn error(&mut self, message: String) -> Label {
+        if let Some((_, stub, _)) = self.messages.iter().find(|(text, ..)| *text == message) {
+            return *stub;
+        }
+        let stub = self.asm.new_label();
+        let text = self.asm.new_label();
+        self.messages.push((message, stub, text));
+        stub
+    }
+
+    /// Appends the helpers, error stubs and messages, and wraps the code in an ELF file.
+    fn finish(mut self) -> Vec<u8> {
+        self.emit_flush();
+        self.emit_put_byte();
+        self.emit_output();
+        self.emit_read_byte();
+
+        let asm = &mut self.asm;
+        asm.bind(self.fail);
+        asm.call(self.flush);
+        // write(2, rbx + 8, [rbx])
+        asm.mov_imm(RAX, SYS_WRITE);
+        asm.mov_imm(RDI, 2);
+        asm.memory_op(LEA, RSI, RBX, 8);
+        asm.memory_op(MOV_LOAD, RDX, RBX, 0);
+        asm.syscall();
+        exit(asm, 1);
+
+        for (_, stub, text) in &self.messages {
+            asm.bind(*stub);
+            asm.lea(RBX, *text);
+            asm.jump(self.fail);
+        }
+        for (message, _, text) in &self.messages {
+            let line = format!("{message}\n");
+            asm.bind(*text);
+            asm.emit(&(line.len() as u64).to_le_bytes());
+            asm.emit(line.as_bytes());
+        }
+
+        let code = self.asm.finish();
+        let mut file = elf_headers(code.len() as u64);
+        file.extend_from_slice(&code);
+        file
+    }
+
+    fn emit_flush(&mut self) {
+        let asm = &mut self.asm;
+        let again = asm.new_label();
+        let failed = asm.new_label();
+        asm.bind(self.flush);
+        asm.mov(RSI, R13);
+        asm.bind(again);
+        asm.test(R12);
+        let done = asm.new_label();
+        asm.jump_if(JZ, done);
+        // write(1, rsi, r12), which may write less than asked for
+        asm.mov_imm(RAX, SYS_WRITE);
+        asm.mov_imm(RDI, 1);
+        asm.mov(RDX, R12);
+        asm.syscall();
+        asm.alu_imm(CMP, RAX, 0);
+        asm.jump_if(JLE, failed);
+        asm.add(RSI, RAX);
+        asm.reg_op(0x29, R12, RAX); // sub r12, rax
+        asm.jump(again);
+        asm.bind(done);
+        asm.ret();
+        asm.bind(failed);
+        exit(asm, 1);
+    }
+
+    fn emit_put_byte(&mut self) {
+        let asm = &mut self.asm;
+        asm.bind(self.put_byte);
+        asm.emit(&[0x43, 0x88, 0x44, 0x25, 0x00]); // mov [r13 + r12], al
+        asm.emit(&[0x49, 0xFF, 0xC4]); // inc r12
+        asm.alu_imm(CMP, R12, BUFFER_SIZE);
+        asm.jump_if(JAE, self.flush);
+        asm.ret();
+    }
+
+    fn emit_output(&mut self) {
+        let asm = &mut self.asm;
+        asm.bind(self.output);
+        if self.options.cell_width == CellWidth::Bits8 {
+            asm.jump(self.put_byte);
+            return;
+        }
+        // Like the interpreter, values that are not characters print as U+FFFD
+        let replace = asm.new_label();
+        let encode = asm.new_label();
+        asm.mov_imm(RCX, char::MAX as u64);
+        asm.cmp(RAX, RCX);
+        asm.jump_if(JA, replace);
+        asm.mov(RCX, RAX);
+        asm.alu_imm(SUB, RCX, 0xD800);
+        asm.alu_imm(CMP, RCX, 0x800);
+        asm.jump_if(JAE, encode);
+        asm.bind(replace);
+        asm.mov_imm(RAX, char::REPLACEMENT_CHARACTER as u64);
+        asm.bind(encode);
+
+        // UTF-8, with the value kept in rbx while the bytes are written
+        let lengths = [asm.new_label(), asm.new_label(), asm.new_label()];
+        asm.mov(RBX, RAX);
+        for (&label, limit) in lengths.iter().zip([0x80, 0x800, 0x10000]) {
+            asm.alu_imm(CMP, RAX, limit);
+            asm.jump_if(JB, label);
+        }
+        for (&label, (shift, lead)) in [None, Some(lengths[2]), Some(lengths[1])].iter().zip([
+            (18, 0xF0),
+            (12, 0xE0),
+            (6, 0xC0),
+        ]) {
+            if let Some(label) = label {
+                asm.bind(label);
+            }
+            asm.mov(RAX, RBX);
+            asm.shr(RAX, shift);
+            asm.alu_imm(OR, RAX, lead);
+            asm.call(self.put_byte);
+            for shift in (0..shift).step_by(6).rev() {
+                asm.mov(RAX, RBX);
+                asm.shr(RAX, shift);
+                asm.alu_imm(AND, RAX, 0x3F);
+                asm.alu_imm(OR, RAX, 0x80);
+                asm.call(self.put_byte);
+            }
+            asm.ret();
+        }
+        asm.bind(lengths[0]);
+        asm.jump(self.put_byte);
+    }
+
+    fn emit_read_byte(&mut self) {
+        let asm = &mut self.asm;
+        let at_end = asm.new_label();
+        asm.bind(self.read_byte);
+        // Show the output before waiting for input
+        asm.call(self.flush);
+        // read(0, r13 + BUFFER_SIZE, 1)
+        asm.mov_imm(RAX, SYS_READ);
+        asm.mov_imm(RDI, 0);
+        asm.mov(RSI, R13);
+        asm.alu_imm(ADD, RSI, BUFFER_SIZE);
+        asm.mov_imm(RDX, 1);
+        asm.syscall();
+        asm.alu_imm(CMP, RAX, 1);
+        asm.jump_if(JNZ, at_end);
+        asm.emit(&[0x0F, 0xB6, 0x06]); // movzx eax, byte [rsi]
+        asm.ret();
+        asm.bind(at_end);
+        asm.mov_imm(RAX, u64::MAX);
+        asm.ret();
+    }
+}
+
+/// `exit_group(status)`
+fn exit(asm: &mut Assembler, status: u64) {
+    asm.mov_imm(RAX, SYS_EXIT_GROUP);
+    asm.mov_imm(RDI, status);
+    asm.syscall();
+}
+
+/// The ELF header and a single program header loading the whole file as code.
+fn elf_headers(code_size: u64) -> Vec<u8> {
+    let file_size = HEADERS_SIZE + code_size;
+    let mut headers = Vec::with_capacity(HEADERS_SIZE as usize);
+    // e_ident: magic, 64-bit, little endian, version 1, System V ABI
+    headers.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    headers.extend_from_slice(&[0; 8]);
+    headers.extend_from_slice(&2u16.to_le_bytes()); // e_type: executable
+    headers.extend_from_slice(&0x3Eu16.to_le_bytes()); // e_machine: x86-64
+    headers.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    headers.extend_from_slice(&(BASE_ADDRESS + HEADERS_SIZE).to_le_bytes()); // e_entry
+    headers.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    headers.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    headers.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    headers.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    headers.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    headers.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    headers.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    headers.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    headers.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    headers.extend_from_slice(&1u32.to_le_bytes()); // p_type: loadable
+    headers.extend_from_slice(&(0x4 | 0x1u32).to_le_bytes()); // p_flags: readable, executable
+    headers.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    headers.extend_from_slice(&BASE_ADDRESS.to_le_bytes()); // p_vaddr
+    headers.extend_from_slice(&BASE_ADDRESS.to_le_bytes()); // p_paddr
+    headers.extend_from_slice(&file_size.to_le_bytes()); // p_filesz
+    headers.extend_from_slice(&file_size.to_le_bytes()); // p_memsz
+    headers.extend_from_slice(&0x1000u64.to_le_bytes()); // p_align
+    headers
+}
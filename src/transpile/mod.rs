@@ -0,0 +1,42 @@
+//! Backends that turn a compiled [`Program`](crate::Program) into source code in another
+//! language, or straight into an executable.
+//!
+//! They work on the optimized instructions, so `[->+<]` becomes one multiply-add
+//! rather than a loop. The generated programs read stdin and write stdout like `bf` does.
+
+mod c;
+mod elf;
+mod rust;
+mod wasm;
+
+pub use c::to_c;
+pub use elf::to_elf;
+pub use rust::to_rust;
+pub use wasm::{to_wasm, to_wat};
+
+use crate::machine::{CellWidth, EofBehavior, Machine, TapeMode};
+
+/// The tape semantics a generated program has, the same ones a [`Machine`] has.
+///
+/// Cells always wrap around on overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranspileOptions {
+    /// Initial number of cells. Unless the tape is circular, it still grows on demand.
+    pub tape_size: usize,
+    pub tape_mode: TapeMode,
+    pub cell_width: CellWidth,
+    /// What `,` does at the end of stdin. [`EofBehavior::Block`] fails like
+    /// [`EofBehavior::Error`], since stdin cannot get more input once it ended.
+    pub eof_behavior: EofBehavior,
+}
+
+impl Default for TranspileOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: Machine::DEFAULT_TAPE_SIZE,
+            tape_mode: TapeMode::default(),
+            cell_width: CellWidth::default(),
+            eof_behavior: EofBehavior::default(),
+        }
+    }
+}
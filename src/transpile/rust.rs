@@ -0,0 +1,329 @@
+use super::TranspileOptions;
+use crate::bytecode::{Instruction, Op, Program};
+use crate::machine::{CellWidth, EofBehavior, TapeMode};
+use std::fmt::Write;
+
+/// Translates `program` into a standalone `main.rs` that builds without warnings
+/// under `clippy::all` and only uses the standard library.
+///
+/// Runtime errors are printed to stderr like `Tried to go out of bounds at character 3`
+/// and exit with status 1, after the output so far has been written.
+pub fn to_rust(program: &Program, options: &TranspileOptions) -> String {
+    let instructions = program.instructions();
+    let uses = |matches: fn(Op) -> bool| instructions.iter().any(|i| matches(i.op));
+    let moves = uses(|op| matches!(op, Op::Move(_) | Op::MulAdd { .. } | Op::Scan(_)));
+    let outputs = uses(|op| op == Op::Output);
+    let inputs = uses(|op| op == Op::Input);
+    let dumps = uses(|op| op == Op::Dump);
+    let origin = dumps || (moves && options.tape_mode == TapeMode::Bidirectional);
+
+    let cell_type = match options.cell_width {
+        CellWidth::Bits8 => "u8",
+        CellWidth::Bits16 => "u16",
+        CellWidth::Bits32 => "u32",
+        CellWidth::Bits64 => "u64",
+    };
+    let mut rust = String::new();
+    let _ = writeln!(rust, "//! Generated from Brainfuck.");
+    let _ = writeln!(rust, "#![warn(clippy::all, rust_2018_idioms)]");
+    let _ = writeln!(rust);
+    if instructions.is_empty() {
+        let _ = writeln!(rust, "fn main() {{}}");
+        return rust;
+    }
+    if inputs {
+        let _ = writeln!(rust, "use std::io::{{self, Read, Write}};");
+    } else {
+        let _ = writeln!(rust, "use std::io::{{self, Write}};");
+    }
+    let _ = writeln!(rust, "use std::process::ExitCode;");
+    let _ = writeln!(rust);
+    let _ = writeln!(rust, "type Cell = {cell_type};");
+    let _ = writeln!(rust);
+    let _ = writeln!(rust, "struct Machine {{");
+    let _ = writeln!(rust, "    tape: Vec<Cell>,");
+    if origin {
+        let _ = writeln!(rust, "    /// Index in `tape` of logical cell 0.");
+        let _ = writeln!(rust, "    origin: usize,");
+    }
+    let _ = writeln!(rust, "    p: usize,");
+    let _ = writeln!(rust, "    output: io::BufWriter<io::Stdout>,");
+    let _ = writeln!(rust, "}}");
+    let _ = writeln!(rust);
+    let mut methods = Vec::new();
+    if moves {
+        methods.push(at_method(options.tape_mode).to_string());
+    }
+    if outputs {
+        methods.push(output_method(options.cell_width).to_string());
+    }
+    if inputs {
+        methods.push(input_method(options.eof_behavior, options.cell_width));
+    }
+    if dumps {
+        methods.push(DUMP.to_string());
+    }
+    if !methods.is_empty() {
+        let methods: Vec<&str> = methods.iter().map(|m| m.trim_start_matches('\n')).collect();
+        let _ = writeln!(rust, "impl Machine {{");
+        let _ = write!(rust, "{}", methods.join("\n"));
+        let _ = writeln!(rust, "}}");
+        let _ = writeln!(rust);
+    }
+
+    let _ = writeln!(rust, "fn main() -> ExitCode {{");
+    let _ = writeln!(rust, "    let mut machine = Machine {{");
+    let _ = writeln!(rust, "        tape: vec![0; {}],", options.tape_size.max(1));
+    if origin {
+        let _ = writeln!(rust, "        origin: 0,");
+    }
+    let _ = writeln!(rust, "        p: 0,");
+    let _ = writeln!(rust, "        output: io::BufWriter::new(io::stdout()),");
+    let _ = writeln!(rust, "    }};");
+    let _ = writeln!(rust, "    let result = run(&mut machine);");
+    let _ = writeln!(
+        rust,
+        "    let flushed = machine.output.flush().map_err(|e| e.to_string());"
+    );
+    let _ = writeln!(rust, "    match result.and(flushed) {{");
+    let _ = writeln!(rust, "        Ok(()) => ExitCode::SUCCESS,");
+    let _ = writeln!(rust, "        Err(e) => {{");
+    let _ = writeln!(rust, "            eprintln!(\"{{e}}\");");
+    let _ = writeln!(rust, "            ExitCode::FAILURE");
+    let _ = writeln!(rust, "        }}");
+    let _ = writeln!(rust, "    }}");
+    let _ = writeln!(rust, "}}");
+    let _ = writeln!(rust);
+
+    let _ = writeln!(rust, "fn run(m: &mut Machine) -> Result<(), String> {{");
+    let mut depth = 1;
+    for (index, instruction) in instructions.iter().enumerate() {
+        let position = instruction.position;
+        // A loop whose body never changes the tape is written as an endless
+        // `loop`, since that is what it is once entered. It takes two levels.
+        let loop_levels = |body: &[Instruction]| if changes_tape(body) { 1 } else { 2 };
+        if let Op::JumpIfNotZero(after_start) = instruction.op {
+            depth -= loop_levels(&instructions[after_start..index]);
+        }
+        let lines = match instruction.op {
+            Op::Add(n) => vec![format!(
+                "m.tape[m.p] = m.tape[m.p].wrapping_add({});",
+                n as u64 & options.cell_width.max_value()
+            )],
+            Op::Move(n) => vec![format!("m.p = m.at({n}, {position})?;")],
+            Op::Output => vec!["m.output()?;".to_string()],
+            Op::Input => vec![format!("m.input({position})?;")],
+            Op::JumpIfZero(after_end) if changes_tape(&instructions[index + 1..after_end - 1]) => {
+                vec!["while m.tape[m.p] != 0 {".to_string()]
+            }
+            Op::JumpIfZero(after_end) => {
+                let mut lines = vec![
+                    "if m.tape[m.p] != 0 {".to_string(),
+                    "    // Nothing in the body changes the tape, so the loop never ends"
+                        .to_string(),
+                    "    loop {".to_string(),
+                ];
+                if after_end - 1 == index + 1 {
+                    lines.push("        std::thread::park();".to_string());
+                }
+                lines
+            }
+            Op::JumpIfNotZero(after_start) if changes_tape(&instructions[after_start..index]) => {
+                vec!["}".to_string()]
+            }
+            Op::JumpIfNotZero(_) => vec!["    }".to_string(), "}".to_string()],
+            Op::SetZero => vec!["m.tape[m.p] = 0;".to_string()],
+            Op::MulAdd { offset, factor } => vec![
+                "if m.tape[m.p] != 0 {".to_string(),
+                format!("    let t = m.at({offset}, {position})?;"),
+                format!(
+                    "    m.tape[t] = m.tape[t].wrapping_add(m.tape[m.p].wrapping_mul({}));",
+                    factor as u64 & options.cell_width.max_value()
+                ),
+                "}".to_string(),
+            ],
+            Op::Scan(step) => vec![
+                "while m.tape[m.p] != 0 {".to_string(),
+                format!("    m.p = m.at({step}, {position})?;"),
+                "}".to_string(),
+            ],
+            Op::Dump => vec!["m.dump();".to_string()],
+        };
+        for line in lines {
+            let _ = writeln!(rust, "{:indent$}{line}", "", indent = depth * 4);
+        }
+        if let Op::JumpIfZero(after_end) = instruction.op {
+            depth += loop_levels(&instructions[index + 1..after_end - 1]);
+        }
+    }
+    let _ = writeln!(rust, "    Ok(())");
+    let _ = writeln!(rust, "}}");
+    rust
+}
+
+/// True if running `body` can change a cell or move the pointer.
+fn changes_tape(body: &[Instruction]) -> bool {
+    body.iter().any(|instruction| {
+        !matches!(
+            instruction.op,
+            Op::Output | Op::Dump | Op::JumpIfZero(_) | Op::JumpIfNotZero(_)
+        )
+    })
+}
+
+/// `at(offset, position)`: the index of the cell `offset` away from the current one,
+/// growing the tape if the mode allows it.
+fn at_method(tape_mode: TapeMode) -> &'static str {
+    match tape_mode {
+        TapeMode::Standard => {
+            r#"
+    fn at(&mut self, offset: isize, position: usize) -> Result<usize, String> {
+        let index = self
+            .p
+            .checked_add_signed(offset)
+            .ok_or_else(|| format!("Tried to go out of bounds at character {position}"))?;
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        Ok(index)
+    }
+"#
+        }
+        TapeMode::Bidirectional => {
+            r#"
+    fn at(&mut self, offset: isize, _position: usize) -> Result<usize, String> {
+        let index = match self.p.checked_add_signed(offset) {
+            Some(index) => index,
+            None => {
+                // Grow the left side at least as much as the tape already is
+                let added = (offset.unsigned_abs() - self.p).max(self.tape.len());
+                self.tape.splice(0..0, vec![0; added]);
+                self.origin += added;
+                self.p += added;
+                self.p - offset.unsigned_abs()
+            }
+        };
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        Ok(index)
+    }
+"#
+        }
+        TapeMode::Circular => {
+            r#"
+    fn at(&mut self, offset: isize, _position: usize) -> Result<usize, String> {
+        let len = self.tape.len() as isize;
+        Ok((self.p as isize + offset % len).rem_euclid(len) as usize)
+    }
+"#
+        }
+    }
+}
+
+/// `output()`: writes the current cell, as a raw byte for 8-bit cells and UTF-8 for wider ones.
+fn output_method(cell_width: CellWidth) -> &'static str {
+    match cell_width {
+        CellWidth::Bits8 => {
+            r#"
+    fn output(&mut self) -> Result<(), String> {
+        let byte = self.tape[self.p];
+        self.output.write_all(&[byte]).map_err(|e| e.to_string())
+    }
+"#
+        }
+        CellWidth::Bits16 => {
+            r#"
+    fn output(&mut self) -> Result<(), String> {
+        let c = char::from_u32(u32::from(self.tape[self.p])).unwrap_or(char::REPLACEMENT_CHARACTER);
+        write!(self.output, "{c}").map_err(|e| e.to_string())
+    }
+"#
+        }
+        CellWidth::Bits32 => {
+            r#"
+    fn output(&mut self) -> Result<(), String> {
+        let c = char::from_u32(self.tape[self.p]).unwrap_or(char::REPLACEMENT_CHARACTER);
+        write!(self.output, "{c}").map_err(|e| e.to_string())
+    }
+"#
+        }
+        CellWidth::Bits64 => {
+            r#"
+    fn output(&mut self) -> Result<(), String> {
+        let c = u32::try_from(self.tape[self.p])
+            .ok()
+            .and_then(char::from_u32)
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        write!(self.output, "{c}").map_err(|e| e.to_string())
+    }
+"#
+        }
+    }
+}
+
+/// `input(position)`: reads one byte of stdin into the current cell.
+fn input_method(eof_behavior: EofBehavior, cell_width: CellWidth) -> String {
+    let position = match eof_behavior {
+        EofBehavior::Block | EofBehavior::Error => "position",
+        _ => "_position",
+    };
+    let at_eof = match eof_behavior {
+        EofBehavior::Unchanged => "{}",
+        EofBehavior::Zero => "self.tape[self.p] = 0,",
+        EofBehavior::MinusOne => "self.tape[self.p] = Cell::MAX,",
+        EofBehavior::Block | EofBehavior::Error => {
+            r#"{
+                return Err(format!(
+                    "Tried to read past the end of input at character {position}"
+                ))
+            }"#
+        }
+    };
+    let byte = if cell_width == CellWidth::Bits8 {
+        "byte[0]"
+    } else {
+        "Cell::from(byte[0])"
+    };
+    format!(
+        r#"
+    fn input(&mut self, {position}: usize) -> Result<(), String> {{
+        self.output.flush().map_err(|e| e.to_string())?;
+        let mut byte = [0];
+        match io::stdin().read(&mut byte) {{
+            Ok(0) => {at_eof}
+            Ok(_) => self.tape[self.p] = {byte},
+            Err(e) => return Err(e.to_string()),
+        }}
+        Ok(())
+    }}
+"#
+    )
+}
+
+/// `dump()`, only emitted for programs compiled with `#` dumps.
+const DUMP: &str = r##"
+    /// Prints the used part of the tape to stderr, like `bf --dump`.
+    fn dump(&mut self) {
+        let _ = self.output.flush();
+        let used = self
+            .tape
+            .iter()
+            .rposition(|&cell| cell != 0)
+            .map_or(0, |last| last + 1)
+            .max(self.p + 1);
+        let cells: Vec<String> = self.tape[..used]
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == self.p {
+                    format!("[{cell}]")
+                } else {
+                    cell.to_string()
+                }
+            })
+            .collect();
+        let cell = self.p as isize - self.origin as isize;
+        eprintln!("#: cell {cell}: {}", cells.join(" "));
+    }
+"##;
@@ -0,0 +1,848 @@
+use super::TranspileOptions;
+use crate::bytecode::{Op, Program};
+use crate::machine::{CellWidth, EofBehavior, TapeMode};
+use std::fmt::Write;
+
+/// Cells in one 64 KiB page of memory, each cell takes 8 bytes.
+const PAGE_CELLS_SHIFT: i32 = 13;
+const PAGE_BYTES_SHIFT: i32 = 16;
+
+/// What the exported `error` global holds after `run` trapped.
+const OUT_OF_BOUNDS: i32 = 1;
+const END_OF_INPUT: i32 = 2;
+const OUT_OF_MEMORY: i32 = 3;
+
+/// Translates `program` into a WebAssembly module in the text format.
+///
+/// See [`to_wasm`] for what the module imports and exports.
+pub fn to_wat(program: &Program, options: &TranspileOptions) -> String {
+    Module::new(program, options).to_wat()
+}
+
+/// Translates `program` into a binary WebAssembly module.
+///
+/// The module imports `env.read_byte: () -> i32`, which returns the next byte of
+/// input or -1 at the end of it, and `env.write_byte: (i32) -> ()`. Cells wider than
+/// 8 bits are written as UTF-8. It exports its `memory`, holding the tape as 64-bit
+/// cells, and `run: () -> ()`, which runs the program on a zeroed tape every time
+/// it is called.
+///
+/// On a runtime error `run` traps with `unreachable`, after setting the exported `error`
+/// global to 1 when moving out of bounds, 2 when reading past the end of input or 3
+/// when the tape cannot grow, and the exported `position` global to the character
+/// the error happened at. `#` dumps are ignored.
+pub fn to_wasm(program: &Program, options: &TranspileOptions) -> Vec<u8> {
+    Module::new(program, options).to_wasm()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+        }
+    }
+}
+
+/// The instructions the backend uses. Locals, globals and functions are named,
+/// which the text format keeps and the binary format turns into indices.
+#[derive(Clone, Copy)]
+enum Instr {
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(&'static str),
+    LocalGet(&'static str),
+    LocalSet(&'static str),
+    LocalTee(&'static str),
+    GlobalGet(&'static str),
+    GlobalSet(&'static str),
+    I32Const(i32),
+    I64Const(i64),
+    /// `i64.load` with no offset.
+    Load,
+    /// `i64.store` with no offset.
+    Store,
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    Unreachable,
+    /// Any instruction without immediates, by name and opcode.
+    Simple(&'static str, u8),
+}
+
+use Instr::*;
+
+const SELECT: Instr = Simple("select", 0x1B);
+const I32_EQZ: Instr = Simple("i32.eqz", 0x45);
+const I32_EQ: Instr = Simple("i32.eq", 0x46);
+const I32_LT_S: Instr = Simple("i32.lt_s", 0x48);
+const I32_GT_U: Instr = Simple("i32.gt_u", 0x4B);
+const I32_GE_U: Instr = Simple("i32.ge_u", 0x4F);
+const I32_OR: Instr = Simple("i32.or", 0x72);
+const I32_ADD: Instr = Simple("i32.add", 0x6A);
+const I32_SUB: Instr = Simple("i32.sub", 0x6B);
+const I32_SHL: Instr = Simple("i32.shl", 0x74);
+const I32_SHR_U: Instr = Simple("i32.shr_u", 0x76);
+const I64_EQZ: Instr = Simple("i64.eqz", 0x50);
+const I64_LT_U: Instr = Simple("i64.lt_u", 0x54);
+const I64_GT_U: Instr = Simple("i64.gt_u", 0x56);
+const I64_ADD: Instr = Simple("i64.add", 0x7C);
+const I64_SUB: Instr = Simple("i64.sub", 0x7D);
+const I64_MUL: Instr = Simple("i64.mul", 0x7E);
+const I64_AND: Instr = Simple("i64.and", 0x83);
+const I64_OR: Instr = Simple("i64.or", 0x84);
+const I64_SHR_U: Instr = Simple("i64.shr_u", 0x88);
+const I32_WRAP_I64: Instr = Simple("i32.wrap_i64", 0xA7);
+const I64_EXTEND_I32_U: Instr = Simple("i64.extend_i32_u", 0xAD);
+
+/// Host functions, all from the `env` module.
+const IMPORTS: [(&str, &[ValType], Option<ValType>); 2] = [
+    ("read_byte", &[], Some(ValType::I32)),
+    ("write_byte", &[ValType::I32], None),
+];
+
+/// Mutable `i32` globals, with the name they are exported as.
+const GLOBALS: [(&str, Option<&str>); 3] = [
+    // The data pointer, in cells
+    ("p", None),
+    ("error", Some("error")),
+    ("position", Some("position")),
+];
+
+struct Function {
+    name: &'static str,
+    export: bool,
+    params: Vec<(&'static str, ValType)>,
+    result: Option<ValType>,
+    locals: Vec<(&'static str, ValType)>,
+    body: Vec<Instr>,
+}
+
+impl Function {
+    fn new(name: &'static str, params: &[(&'static str, ValType)]) -> Self {
+        Self {
+            name,
+            export: false,
+            params: params.to_vec(),
+            result: None,
+            locals: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn local_index(&self, name: &str) -> u32 {
+        let position = self
+            .params
+            .iter()
+            .chain(&self.locals)
+            .position(|&(local, _)| local == name);
+        position.expect("the local is declared") as u32
+    }
+}
+
+struct Module {
+    initial_pages: u32,
+    functions: Vec<Function>,
+}
+
+impl Module {
+    fn new(program: &Program, options: &TranspileOptions) -> Self {
+        let tape_size = options.tape_size.max(1);
+        Self {
+            initial_pages: (tape_size * 8).div_ceil(1 << PAGE_BYTES_SHIFT) as u32,
+            functions: vec![
+                fail(),
+                grow(),
+                at(options.tape_mode, tape_size as i32),
+                output(options.cell_width),
+                input(options.eof_behavior, options.cell_width),
+                run(program, options),
+            ],
+        }
+    }
+
+    fn function_index(&self, name: &str) -> u32 {
+        let imports = IMPORTS.iter().map(|&(import, ..)| import);
+        let functions = self.functions.iter().map(|function| function.name);
+        let position = imports
+            .chain(functions)
+            .position(|function| function == name);
+        position.expect("the function exists") as u32
+    }
+
+    fn to_wat(&self) -> String {
+        let mut wat = String::new();
+        let _ = writeln!(wat, ";; Generated from Brainfuck.");
+        let _ = writeln!(wat, "(module");
+        for (name, params, result) in IMPORTS {
+            let _ = writeln!(
+                wat,
+                "  (import \"env\" \"{name}\" (func ${name}{}))",
+                signature(&[], params, result)
+            );
+        }
+        let _ = writeln!(wat, "  (memory (export \"memory\") {})", self.initial_pages);
+        for (name, export) in GLOBALS {
+            let export = export.map_or(String::new(), |export| format!(" (export \"{export}\")"));
+            let _ = writeln!(wat, "  (global ${name}{export} (mut i32) (i32.const 0))");
+        }
+        for function in &self.functions {
+            let names: Vec<&str> = function.params.iter().map(|&(name, _)| name).collect();
+            let types: Vec<ValType> = function.params.iter().map(|&(_, ty)| ty).collect();
+            let export = if function.export {
+                format!(" (export \"{}\")", function.name)
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                wat,
+                "  (func ${}{export}{}",
+                function.name,
+                signature(&names, &types, function.result)
+            );
+            for (name, ty) in &function.locals {
+                let _ = writeln!(wat, "    (local ${name} {})", ty.name());
+            }
+            let mut depth = 2;
+            for instruction in &function.body {
+                if matches!(instruction, End) {
+                    depth -= 1;
+                }
+                let _ = writeln!(
+                    wat,
+                    "{:indent$}{}",
+                    "",
+                    text(instruction),
+                    indent = depth * 2
+                );
+                if matches!(instruction, Block | Loop | If) {
+                    depth += 1;
+                }
+            }
+            let _ = writeln!(wat, "  )");
+        }
+        let _ = writeln!(wat, ")");
+        wat
+    }
+
+    fn to_wasm(&self) -> Vec<u8> {
+        let mut wasm = b"\0asm".to_vec();
+        wasm.extend_from_slice(&1u32.to_le_bytes());
+
+        // Every import and function gets its own type, in order
+        let mut types = Vec::new();
+        let signatures = IMPORTS
+            .iter()
+            .map(|&(_, params, result)| (params.to_vec(), result));
+        let signatures = signatures.chain(self.functions.iter().map(|function| {
+            let params = function.params.iter().map(|&(_, ty)| ty).collect();
+            (params, function.result)
+        }));
+        let mut count = 0;
+        for (params, result) in signatures {
+            types.push(0x60);
+            unsigned(&mut types, params.len() as u64);
+            types.extend(params.iter().map(|ty| ty.code()));
+            unsigned(&mut types, result.is_some() as u64);
+            types.extend(result.map(ValType::code));
+            count += 1;
+        }
+        section(&mut wasm, 1, count, &types);
+
+        let mut imports = Vec::new();
+        for (index, (name, ..)) in IMPORTS.iter().enumerate() {
+            string(&mut imports, "env");
+            string(&mut imports, name);
+            imports.push(0x00);
+            unsigned(&mut imports, index as u64);
+        }
+        section(&mut wasm, 2, IMPORTS.len(), &imports);
+
+        let mut functions = Vec::new();
+        for index in 0..self.functions.len() {
+            unsigned(&mut functions, (IMPORTS.len() + index) as u64);
+        }
+        section(&mut wasm, 3, self.functions.len(), &functions);
+
+        let mut memory = vec![0x00];
+        unsigned(&mut memory, self.initial_pages as u64);
+        section(&mut wasm, 5, 1, &memory);
+
+        let mut globals = Vec::new();
+        for _ in GLOBALS {
+            globals.extend_from_slice(&[ValType::I32.code(), 0x01, 0x41, 0x00, 0x0B]);
+        }
+        section(&mut wasm, 6, GLOBALS.len(), &globals);
+
+        let mut exports = Vec::new();
+        let mut count = 1;
+        string(&mut exports, "memory");
+        exports.extend_from_slice(&[0x02, 0x00]);
+        for (index, (_, export)) in GLOBALS.iter().enumerate() {
+            if let Some(export) = export {
+                string(&mut exports, export);
+                exports.push(0x03);
+                unsigned(&mut exports, index as u64);
+                count += 1;
+            }
+        }
+        for function in self.functions.iter().filter(|function| function.export) {
+            string(&mut exports, function.name);
+            exports.push(0x00);
+            unsigned(&mut exports, self.function_index(function.name) as u64);
+            count += 1;
+        }
+        section(&mut wasm, 7, count, &exports);
+
+        let mut code = Vec::new();
+        for function in &self.functions {
+            let mut body = Vec::new();
+            unsigned(&mut body, function.locals.len() as u64);
+            for (_, ty) in &function.locals {
+                body.extend_from_slice(&[0x01, ty.code()]);
+            }
+            for instruction in &function.body {
+                self.encode(&mut body, function, instruction);
+            }
+            body.push(0x0B);
+            unsigned(&mut code, body.len() as u64);
+            code.extend_from_slice(&body);
+        }
+        section(&mut wasm, 10, self.functions.len(), &code);
+        wasm
+    }
+
+    fn encode(&self, out: &mut Vec<u8>, function: &Function, instruction: &Instr) {
+        let global = |name: &str| {
+            let position = GLOBALS.iter().position(|&(global, _)| global == name);
+            position.expect("the global exists") as u64
+        };
+        match *instruction {
+            Block => out.extend_from_slice(&[0x02, 0x40]),
+            Loop => out.extend_from_slice(&[0x03, 0x40]),
+            If => out.extend_from_slice(&[0x04, 0x40]),
+            End => out.push(0x0B),
+            Br(depth) => {
+                out.push(0x0C);
+                unsigned(out, depth as u64);
+            }
+            BrIf(depth) => {
+                out.push(0x0D);
+                unsigned(out, depth as u64);
+            }
+            Return => out.push(0x0F),
+            Call(name) => {
+                out.push(0x10);
+                unsigned(out, self.function_index(name) as u64);
+            }
+            LocalGet(name) | LocalSet(name) | LocalTee(name) => {
+                out.push(match instruction {
+                    LocalGet(_) => 0x20,
+                    LocalSet(_) => 0x21,
+                    _ => 0x22,
+                });
+                unsigned(out, function.local_index(name) as u64);
+            }
+            GlobalGet(name) | GlobalSet(name) => {
+                out.push(if matches!(instruction, GlobalGet(_)) {
+                    0x23
+                } else {
+                    0x24
+                });
+                unsigned(out, global(name));
+            }
+            I32Const(value) => {
+                out.push(0x41);
+                signed(out, value as i64);
+            }
+            I64Const(value) => {
+                out.push(0x42);
+                signed(out, value);
+            }
+            // Aligned to 8 bytes, no offset
+            Load => out.extend_from_slice(&[0x29, 0x03, 0x00]),
+            Store => out.extend_from_slice(&[0x37, 0x03, 0x00]),
+            MemorySize => out.extend_from_slice(&[0x3F, 0x00]),
+            MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+            MemoryCopy => out.extend_from_slice(&[0xFC, 0x0A, 0x00, 0x00]),
+            MemoryFill => out.extend_from_slice(&[0xFC, 0x0B, 0x00]),
+            Unreachable => out.push(0x00),
+            Simple(_, opcode) => out.push(opcode),
+        }
+    }
+}
+
+/// `(param $a i32) (result i32)`, with names if there are any.
+fn signature(names: &[&str], params: &[ValType], result: Option<ValType>) -> String {
+    let mut signature = String::new();
+    for (index, ty) in params.iter().enumerate() {
+        match names.get(index) {
+            Some(name) => {
+                let _ = write!(signature, " (param ${name} {})", ty.name());
+            }
+            None => {
+                let _ = write!(signature, " (param {})", ty.name());
+            }
+        }
+    }
+    if let Some(result) = result {
+        let _ = write!(signature, " (result {})", result.name());
+    }
+    signature
+}
+
+fn text(instruction: &Instr) -> String {
+    match *instruction {
+        Block => "block".to_string(),
+        Loop => "loop".to_string(),
+        If => "if".to_string(),
+        End => "end".to_string(),
+        Br(depth) => format!("br {depth}"),
+        BrIf(depth) => format!("br_if {depth}"),
+        Return => "return".to_string(),
+        Call(name) => format!("call ${name}"),
+        LocalGet(name) => format!("local.get ${name}"),
+        LocalSet(name) => format!("local.set ${name}"),
+        LocalTee(name) => format!("local.tee ${name}"),
+        GlobalGet(name) => format!("global.get ${name}"),
+        GlobalSet(name) => format!("global.set ${name}"),
+        I32Const(value) => format!("i32.const {value}"),
+        I64Const(value) => format!("i64.const {value}"),
+        Load => "i64.load".to_string(),
+        Store => "i64.store".to_string(),
+        MemorySize => "memory.size".to_string(),
+        MemoryGrow => "memory.grow".to_string(),
+        MemoryCopy => "memory.copy".to_string(),
+        MemoryFill => "memory.fill".to_string(),
+        Unreachable => "unreachable".to_string(),
+        Simple(name, _) => name.to_string(),
+    }
+}
+
+/// Appends a section with `count` entries.
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: &[u8]) {
+    let mut content = Vec::new();
+    unsigned(&mut content, count as u64);
+    content.extend_from_slice(entries);
+    out.push(id);
+    unsigned(out, content.len() as u64);
+    out.extend_from_slice(&content);
+}
+
+fn string(out: &mut Vec<u8>, value: &str) {
+    unsigned(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Unsigned LEB128.
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128.
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// `fail(code, position)`: records the error and traps.
+fn fail() -> Function {
+    let mut function = Function::new(
+        "fail",
+        &[("code", ValType::I32), ("position", ValType::I32)],
+    );
+    function.body = vec![
+        LocalGet("code"),
+        GlobalSet("error"),
+        LocalGet("position"),
+        GlobalSet("position"),
+        Unreachable,
+    ];
+    function
+}
+
+/// `grow(pages, position)`: adds zeroed pages at the end of the memory.
+fn grow() -> Function {
+    let mut function = Function::new(
+        "grow",
+        &[("pages", ValType::I32), ("position", ValType::I32)],
+    );
+    function.body = vec![
+        LocalGet("pages"),
+        MemoryGrow,
+        I32Const(-1),
+        I32_EQ,
+        If,
+        I32Const(OUT_OF_MEMORY),
+        LocalGet("position"),
+        Call("fail"),
+        End,
+    ];
+    function
+}
+
+/// `at(offset, position)`: the index of the cell `offset` away from the current one,
+/// growing the tape if the mode allows it. On a circular tape `offset` is already
+/// reduced to `0..tape_size`.
+fn at(tape_mode: TapeMode, tape_size: i32) -> Function {
+    let mut function = Function::new(
+        "at",
+        &[("offset", ValType::I32), ("position", ValType::I32)],
+    );
+    function.result = Some(ValType::I32);
+    function.locals = vec![("index", ValType::I32), ("pages", ValType::I32)];
+    let body = &mut function.body;
+    body.extend([
+        GlobalGet("p"),
+        LocalGet("offset"),
+        I32_ADD,
+        LocalSet("index"),
+    ]);
+    if tape_mode == TapeMode::Circular {
+        body.extend([
+            LocalGet("index"),
+            I32Const(tape_size),
+            I32_GE_U,
+            If,
+            LocalGet("index"),
+            I32Const(tape_size),
+            I32_SUB,
+            LocalSet("index"),
+            End,
+            LocalGet("index"),
+        ]);
+        return function;
+    }
+
+    body.extend([LocalGet("index"), I32Const(0), I32_LT_S, If]);
+    if tape_mode == TapeMode::Bidirectional {
+        function.locals.push(("old_pages", ValType::I32));
+        let body = &mut function.body;
+        body.extend([
+            // Grow the left side at least as much as the tape already is
+            I32Const(0),
+            LocalGet("index"),
+            I32_SUB,
+            I32Const((1 << PAGE_CELLS_SHIFT) - 1),
+            I32_ADD,
+            I32Const(PAGE_CELLS_SHIFT),
+            I32_SHR_U,
+            LocalSet("pages"),
+        ]);
+        at_least_memory_size(body);
+        body.extend([
+            MemorySize,
+            LocalSet("old_pages"),
+            LocalGet("pages"),
+            LocalGet("position"),
+            Call("grow"),
+            // Move the tape up and clear the new cells in front of it
+            LocalGet("pages"),
+            I32Const(PAGE_BYTES_SHIFT),
+            I32_SHL,
+            I32Const(0),
+            LocalGet("old_pages"),
+            I32Const(PAGE_BYTES_SHIFT),
+            I32_SHL,
+            MemoryCopy,
+            I32Const(0),
+            I32Const(0),
+            LocalGet("pages"),
+            I32Const(PAGE_BYTES_SHIFT),
+            I32_SHL,
+            MemoryFill,
+            GlobalGet("p"),
+            LocalGet("pages"),
+            I32Const(PAGE_CELLS_SHIFT),
+            I32_SHL,
+            I32_ADD,
+            GlobalSet("p"),
+            LocalGet("index"),
+            LocalGet("pages"),
+            I32Const(PAGE_CELLS_SHIFT),
+            I32_SHL,
+            I32_ADD,
+            LocalSet("index"),
+        ]);
+    } else {
+        body.extend([I32Const(OUT_OF_BOUNDS), LocalGet("position"), Call("fail")]);
+    }
+
+    let body = &mut function.body;
+    body.extend([
+        End,
+        // Past the right end, grow to fit the cell and at least double
+        LocalGet("index"),
+        MemorySize,
+        I32Const(PAGE_CELLS_SHIFT),
+        I32_SHL,
+        I32_GE_U,
+        If,
+        LocalGet("index"),
+        I32Const(PAGE_CELLS_SHIFT),
+        I32_SHR_U,
+        I32Const(1),
+        I32_ADD,
+        MemorySize,
+        I32_SUB,
+        LocalSet("pages"),
+    ]);
+    at_least_memory_size(body);
+    body.extend([
+        LocalGet("pages"),
+        LocalGet("position"),
+        Call("grow"),
+        End,
+        LocalGet("index"),
+    ]);
+    function
+}
+
+/// `pages = max(pages, memory.size)`
+fn at_least_memory_size(body: &mut Vec<Instr>) {
+    body.extend([
+        LocalGet("pages"),
+        MemorySize,
+        LocalGet("pages"),
+        MemorySize,
+        I32_GT_U,
+        SELECT,
+        LocalSet("pages"),
+    ]);
+}
+
+/// `output(value)`: a raw byte for 8-bit cells, UTF-8 for wider ones.
+fn output(cell_width: CellWidth) -> Function {
+    let mut function = Function::new("output", &[("value", ValType::I64)]);
+    let body = &mut function.body;
+    if cell_width == CellWidth::Bits8 {
+        body.extend([LocalGet("value"), I32_WRAP_I64, Call("write_byte")]);
+        return function;
+    }
+    // Like the interpreter, values that are not characters print as U+FFFD
+    body.extend([
+        LocalGet("value"),
+        I64Const(char::MAX as i64),
+        I64_GT_U,
+        LocalGet("value"),
+        I64Const(0xD800),
+        I64_SUB,
+        I64Const(0x800),
+        I64_LT_U,
+        I32_OR,
+        If,
+        I64Const(char::REPLACEMENT_CHARACTER as i64),
+        LocalSet("value"),
+        End,
+    ]);
+    let byte = |body: &mut Vec<Instr>, shift: i64, mask: i64, prefix: i64| {
+        body.extend([
+            LocalGet("value"),
+            I64Const(shift),
+            I64_SHR_U,
+            I64Const(mask),
+            I64_AND,
+            I64Const(prefix),
+            I64_OR,
+            I32_WRAP_I64,
+            Call("write_byte"),
+        ]);
+    };
+    let encodings = [
+        (0x80, 0, 0x7F, 0),
+        (0x800, 6, 0x1F, 0xC0),
+        (0x10000, 12, 0x0F, 0xE0),
+    ];
+    for (limit, shift, mask, prefix) in encodings {
+        body.extend([LocalGet("value"), I64Const(limit), I64_LT_U, If]);
+        byte(body, shift, mask, prefix);
+        for shift in (0..shift / 6).rev().map(|i| i * 6) {
+            byte(body, shift, 0x3F, 0x80);
+        }
+        body.extend([Return, End]);
+    }
+    byte(body, 18, 0x07, 0xF0);
+    for shift in [12, 6, 0] {
+        byte(body, shift, 0x3F, 0x80);
+    }
+    function
+}
+
+/// `input(position)`: reads one byte of input into the current cell.
+fn input(eof_behavior: EofBehavior, cell_width: CellWidth) -> Function {
+    let mut function = Function::new("input", &[("position", ValType::I32)]);
+    function.locals = vec![("byte", ValType::I32)];
+    let body = &mut function.body;
+    body.extend([
+        Call("read_byte"),
+        LocalTee("byte"),
+        I32Const(0),
+        I32_LT_S,
+        If,
+    ]);
+    match eof_behavior {
+        EofBehavior::Unchanged => body.push(Return),
+        EofBehavior::Zero => body.extend([I32Const(0), LocalSet("byte")]),
+        EofBehavior::MinusOne => {}
+        EofBehavior::Block | EofBehavior::Error => {
+            body.extend([I32Const(END_OF_INPUT), LocalGet("position"), Call("fail")])
+        }
+    }
+    if eof_behavior == EofBehavior::MinusOne {
+        cell_address(body);
+        body.extend([I64Const(cell_width.max_value() as i64), Store, Return]);
+    }
+    body.push(End);
+    cell_address(body);
+    body.extend([LocalGet("byte"), I64_EXTEND_I32_U, Store]);
+    function
+}
+
+/// The exported `run`, the program itself.
+fn run(program: &Program, options: &TranspileOptions) -> Function {
+    let mut function = Function::new("run", &[]);
+    function.export = true;
+    function.locals = vec![("address", ValType::I32), ("value", ValType::I64)];
+    let mask = |body: &mut Vec<Instr>| {
+        if options.cell_width != CellWidth::Bits64 {
+            body.extend([I64Const(options.cell_width.max_value() as i64), I64_AND]);
+        }
+    };
+    // Offsets on a circular tape are reduced here so `at` only needs one compare.
+    // Any other offset past an `i32` is further than a 32-bit memory reaches.
+    let offset = |offset: isize, position: Instr| match options.tape_mode {
+        TapeMode::Circular => {
+            let len = options.tape_size.max(1) as isize;
+            vec![I32Const(offset.rem_euclid(len) as i32)]
+        }
+        _ => match i32::try_from(offset) {
+            Ok(offset) => vec![I32Const(offset)],
+            Err(_) => {
+                let code = if options.tape_mode == TapeMode::Standard && offset < 0 {
+                    OUT_OF_BOUNDS
+                } else {
+                    OUT_OF_MEMORY
+                };
+                // `fail` traps, the constant only keeps the code after it valid
+                vec![I32Const(code), position, Call("fail"), I32Const(0)]
+            }
+        },
+    };
+
+    // The tape starts zeroed on every call, not just the first
+    let body = &mut function.body;
+    body.extend([
+        I32Const(0),
+        I32Const(0),
+        MemorySize,
+        I32Const(PAGE_BYTES_SHIFT),
+    ]);
+    body.extend([I32_SHL, MemoryFill, I32Const(0), GlobalSet("p")]);
+    for instruction in program.instructions() {
+        let position = I32Const(instruction.position as i32);
+        match instruction.op {
+            Op::Add(n) => {
+                cell_address(body);
+                body.extend([LocalTee("address"), LocalGet("address"), Load]);
+                body.extend([I64Const(n), I64_ADD]);
+                mask(body);
+                body.push(Store);
+            }
+            Op::Move(n) => {
+                body.extend(offset(n, position));
+                body.extend([position, Call("at"), GlobalSet("p")]);
+            }
+            Op::Output => {
+                load_cell(body);
+                body.push(Call("output"));
+            }
+            Op::Input => body.extend([position, Call("input")]),
+            Op::JumpIfZero(_) => {
+                body.extend([Block]);
+                load_cell(body);
+                body.extend([I64_EQZ, BrIf(0), Loop]);
+            }
+            Op::JumpIfNotZero(_) => {
+                load_cell(body);
+                body.extend([I64_EQZ, I32_EQZ, BrIf(0), End, End]);
+            }
+            Op::SetZero => {
+                cell_address(body);
+                body.extend([I64Const(0), Store]);
+            }
+            Op::MulAdd {
+                offset: target,
+                factor,
+            } => {
+                load_cell(body);
+                body.extend([LocalTee("value"), I64_EQZ, I32_EQZ, If]);
+                body.extend(offset(target, position));
+                body.extend([position, Call("at")]);
+                body.extend([
+                    I32Const(3),
+                    I32_SHL,
+                    LocalTee("address"),
+                    LocalGet("address"),
+                ]);
+                body.extend([Load, LocalGet("value"), I64Const(factor), I64_MUL, I64_ADD]);
+                mask(body);
+                body.extend([Store, End]);
+            }
+            Op::Scan(step) => {
+                body.extend([Block, Loop]);
+                load_cell(body);
+                body.extend([I64_EQZ, BrIf(1)]);
+                body.extend(offset(step, position));
+                body.extend([position, Call("at")]);
+                body.extend([GlobalSet("p"), Br(0), End, End]);
+            }
+            Op::Dump => {}
+        }
+    }
+    function
+}
+
+/// Pushes the byte address of the current cell.
+fn cell_address(body: &mut Vec<Instr>) {
+    body.extend([GlobalGet("p"), I32Const(3), I32_SHL]);
+}
+
+fn load_cell(body: &mut Vec<Instr>) {
+    cell_address(body);
+    body.push(Load);
+}
@@ -0,0 +1,214 @@
+//! A tiny x86-64 assembler for the handful of instructions the native backends need.
+//!
+//! The generated code keeps the tape address in `r8`, the tape length in cells in
+//! `r9` and the data pointer in `r10`. Cells are always 64 bits in memory and are
+//! masked to the cell width after arithmetic.
+
+use crate::machine::CellWidth;
+
+pub(crate) const RAX: u8 = 0;
+pub(crate) const RCX: u8 = 1;
+pub(crate) const RDX: u8 = 2;
+pub(crate) const RBX: u8 = 3;
+pub(crate) const RSI: u8 = 6;
+pub(crate) const RDI: u8 = 7;
+/// Tape address.
+pub(crate) const R8: u8 = 8;
+/// Tape length.
+pub(crate) const R9: u8 = 9;
+/// Data pointer.
+pub(crate) const R10: u8 = 10;
+pub(crate) const R12: u8 = 12;
+pub(crate) const R13: u8 = 13;
+
+pub(crate) const JB: u8 = 0x82;
+pub(crate) const JAE: u8 = 0x83;
+pub(crate) const JZ: u8 = 0x84;
+pub(crate) const JNZ: u8 = 0x85;
+pub(crate) const JA: u8 = 0x87;
+pub(crate) const JS: u8 = 0x88;
+pub(crate) const JLE: u8 = 0x8E;
+
+/// `/digit` extensions of the `op r/m64, imm32` instructions.
+pub(crate) const ADD: u8 = 0;
+pub(crate) const OR: u8 = 1;
+pub(crate) const AND: u8 = 4;
+pub(crate) const SUB: u8 = 5;
+pub(crate) const CMP: u8 = 7;
+
+pub(crate) type Label = usize;
+
+#[derive(Default)]
+pub(crate) struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// Positions of rel32 operands and the label they point to.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub(crate) fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    pub(crate) fn bind(&mut self, label: Label) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    /// Where `label` was bound, as an offset into the code.
+    #[cfg_attr(not(feature = "jit"), allow(dead_code))]
+    pub(crate) fn offset(&self, label: Label) -> usize {
+        self.labels[label].expect("the label is bound")
+    }
+
+    pub(crate) fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// A placeholder for the distance from the end of the instruction to `label`.
+    /// Only valid as the last operand of an instruction.
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    /// REX prefix with W set for the `reg`, `index` and `base` (or r/m) registers.
+    pub(crate) fn rex(&mut self, reg: u8, index: u8, base: u8) {
+        self.emit(&[0x48 | (reg >> 3) << 2 | (index >> 3) << 1 | base >> 3]);
+    }
+
+    /// `opcode reg, [base + offset]`, `base` must not be `rsp` or `r12`.
+    pub(crate) fn memory_op(&mut self, opcode: u8, reg: u8, base: u8, offset: i8) {
+        self.rex(reg, 0, base);
+        self.emit(&[opcode, 0x40 | (reg & 7) << 3 | (base & 7), offset as u8]);
+    }
+
+    /// `opcode reg, [r8 + index * 8]`, for loads, stores and adds of cells.
+    pub(crate) fn cell_op(&mut self, opcode: u8, reg: u8, index: u8) {
+        self.rex(reg, index, R8);
+        self.emit(&[
+            opcode,
+            (reg & 7) << 3 | 0b100,
+            0b11 << 6 | (index & 7) << 3 | (R8 & 7),
+        ]);
+    }
+
+    pub(crate) fn load_cell(&mut self, reg: u8, index: u8) {
+        self.cell_op(0x8B, reg, index);
+    }
+
+    pub(crate) fn store_cell(&mut self, index: u8, reg: u8) {
+        self.cell_op(0x89, reg, index);
+    }
+
+    /// `opcode dst, src` for register to register instructions in the `op r/m64, r64` form.
+    pub(crate) fn reg_op(&mut self, opcode: u8, dst: u8, src: u8) {
+        self.rex(src, 0, dst);
+        self.emit(&[opcode, 0xC0 | (src & 7) << 3 | (dst & 7)]);
+    }
+
+    pub(crate) fn mov(&mut self, dst: u8, src: u8) {
+        self.reg_op(0x89, dst, src);
+    }
+
+    pub(crate) fn add(&mut self, dst: u8, src: u8) {
+        self.reg_op(0x01, dst, src);
+    }
+
+    /// `cmp a, b`, flags as for `a - b`.
+    pub(crate) fn cmp(&mut self, a: u8, b: u8) {
+        self.reg_op(0x39, a, b);
+    }
+
+    pub(crate) fn test(&mut self, reg: u8) {
+        self.reg_op(0x85, reg, reg);
+    }
+
+    /// `imul dst, src`
+    pub(crate) fn imul(&mut self, dst: u8, src: u8) {
+        self.rex(dst, 0, src);
+        self.emit(&[0x0F, 0xAF, 0xC0 | (dst & 7) << 3 | (src & 7)]);
+    }
+
+    /// One of [`ADD`], [`OR`], [`AND`], [`SUB`] or [`CMP`] with a sign-extended immediate.
+    pub(crate) fn alu_imm(&mut self, operation: u8, reg: u8, value: i32) {
+        self.rex(0, 0, reg);
+        self.emit(&[0x81, 0xC0 | operation << 3 | (reg & 7)]);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `shr reg, count`
+    pub(crate) fn shr(&mut self, reg: u8, count: u8) {
+        self.rex(0, 0, reg);
+        self.emit(&[0xC1, 0xE8 | (reg & 7), count]);
+    }
+
+    /// `mov reg, value`
+    pub(crate) fn mov_imm(&mut self, reg: u8, value: u64) {
+        self.rex(0, 0, reg);
+        self.emit(&[0xB8 + (reg & 7)]);
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// `lea reg, [rip + label]`
+    pub(crate) fn lea(&mut self, reg: u8, label: Label) {
+        self.rex(reg, 0, 0);
+        self.emit(&[0x8D, (reg & 7) << 3 | 0b101]);
+        self.rel32(label);
+    }
+
+    pub(crate) fn jump(&mut self, label: Label) {
+        self.emit(&[0xE9]);
+        self.rel32(label);
+    }
+
+    pub(crate) fn jump_if(&mut self, condition: u8, label: Label) {
+        self.emit(&[0x0F, condition]);
+        self.rel32(label);
+    }
+
+    pub(crate) fn call(&mut self, label: Label) {
+        self.emit(&[0xE8]);
+        self.rel32(label);
+    }
+
+    pub(crate) fn ret(&mut self) {
+        self.emit(&[0xC3]);
+    }
+
+    pub(crate) fn syscall(&mut self) {
+        self.emit(&[0x0F, 0x05]);
+    }
+
+    /// Truncates `rax` to the cell width.
+    pub(crate) fn mask(&mut self, cell_width: CellWidth) {
+        match cell_width {
+            CellWidth::Bits8 => self.emit(&[0x0F, 0xB6, 0xC0]), // movzx eax, al
+            CellWidth::Bits16 => self.emit(&[0x0F, 0xB7, 0xC0]), // movzx eax, ax
+            CellWidth::Bits32 => self.emit(&[0x89, 0xC0]),      // mov eax, eax
+            CellWidth::Bits64 => {}
+        }
+    }
+
+    /// `dst = r10 + offset`, then jumps to `outside` unless `dst` is inside the tape.
+    /// Clobbers `rdx`.
+    pub(crate) fn offset_pointer(&mut self, dst: u8, offset: isize, outside: Label) {
+        self.mov(dst, R10);
+        self.mov_imm(RDX, offset as u64);
+        self.add(dst, RDX);
+        // Negative indices wrap around to huge ones, so one unsigned compare checks both ends
+        self.cmp(dst, R9);
+        self.jump_if(JAE, outside);
+    }
+
+    /// The code with every jump resolved.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        for &(position, label) in &self.fixups {
+            let target = self.labels[label].expect("every label is bound");
+            let relative = target as i64 - (position as i64 + 4);
+            self.code[position..position + 4].copy_from_slice(&(relative as i32).to_le_bytes());
+        }
+        self.code
+    }
+}